@@ -0,0 +1,476 @@
+use crate::encoding;
+use encoding_rs::Encoding;
+use log::{debug, info, warn};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use unrar::error::{Code, UnrarError};
+use unrar::Archive;
+
+type Result<T> = std::result::Result<T, ArchiveError>;
+
+/// why an archive couldn't be extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Corrupt,
+    Encrypted,
+    Unsupported,
+    Io,
+    /// extracted, but it has no charts
+    NoCharts,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            ErrorKind::Corrupt => "corrupt",
+            ErrorKind::Encrypted => "encrypted",
+            ErrorKind::Unsupported => "unsupported",
+            ErrorKind::Io => "io",
+            ErrorKind::NoCharts => "no charts",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug)]
+pub struct ArchiveError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl ArchiveError {
+    pub fn new(kind: ErrorKind, message: impl ToString) -> Self {
+        ArchiveError {
+            kind,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.kind, self.message)
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl From<io::Error> for ArchiveError {
+    fn from(e: io::Error) -> Self {
+        let kind = match e.kind() {
+            // broken data is reported as io errors by tar, lzh and gzip
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => ErrorKind::Corrupt,
+            _ => ErrorKind::Io,
+        };
+        ArchiveError::new(kind, e)
+    }
+}
+
+impl From<zip::result::ZipError> for ArchiveError {
+    fn from(e: zip::result::ZipError) -> Self {
+        use zip::result::ZipError;
+        match e {
+            ZipError::Io(e) => e.into(),
+            ZipError::UnsupportedArchive(msg)
+                if msg == ZipError::PASSWORD_REQUIRED || msg.contains("encrypted") =>
+            {
+                ArchiveError::new(ErrorKind::Encrypted, e)
+            }
+            ZipError::UnsupportedArchive(_) => ArchiveError::new(ErrorKind::Unsupported, e),
+            _ => ArchiveError::new(ErrorKind::Corrupt, e),
+        }
+    }
+}
+
+impl From<UnrarError> for ArchiveError {
+    fn from(e: UnrarError) -> Self {
+        let kind = match e.code {
+            Code::MissingPassword | Code::BadPassword => ErrorKind::Encrypted,
+            Code::UnknownFormat => ErrorKind::Unsupported,
+            Code::EOpen | Code::ECreate | Code::EClose | Code::ERead | Code::EWrite => {
+                ErrorKind::Io
+            }
+            _ => ErrorKind::Corrupt,
+        };
+        ArchiveError::new(kind, e)
+    }
+}
+
+impl From<sevenz_rust::Error> for ArchiveError {
+    fn from(e: sevenz_rust::Error) -> Self {
+        use sevenz_rust::Error;
+        let kind = match e {
+            Error::Io(e, _) | Error::FileOpen(e, _) => return e.into(),
+            Error::PasswordRequired | Error::MaybeBadPassword(_) => ErrorKind::Encrypted,
+            Error::UnsupportedVersion { .. }
+            | Error::UnsupportedCompressionMethod(_)
+            | Error::Unsupported(_)
+            | Error::ExternalUnsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Corrupt,
+        };
+        ArchiveError::new(kind, e)
+    }
+}
+
+/// archive formats supported by install
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Rar,
+    SevenZip,
+    Lzh,
+    Tar,
+    TarGz,
+}
+
+/// extensions of the archives install extracts
+const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "rar", "7z", "lzh", "lha", "tar", "gz", "tgz"];
+/// files in zip or gzip format which are not packages
+const NON_ARCHIVE_EXTENSIONS: &[&str] = &[
+    "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "jar", "apk", "svgz",
+];
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+pub fn has_archive_extension(path: &Path) -> bool {
+    ARCHIVE_EXTENSIONS.contains(&extension(path).as_str())
+}
+
+// e.g.) documents, or .gz of a single file other than .tar.gz
+fn has_non_archive_extension(path: &Path) -> bool {
+    let ext = extension(path);
+    let tar_gz = path
+        .file_stem()
+        .is_some_and(|s| s.to_string_lossy().to_lowercase().ends_with(".tar"));
+    NON_ARCHIVE_EXTENSIONS.contains(&ext.as_str()) || (ext == "gz" && !tar_gz)
+}
+
+impl ArchiveFormat {
+    /// detect the format by magic bytes, as extensions are often wrong.
+    /// files whose extensions are known as other formats are skipped
+    pub fn detect(path: &Path) -> Option<Self> {
+        if has_non_archive_extension(path) {
+            return None;
+        }
+        let mut buf = Vec::with_capacity(512);
+        fs::File::open(path)
+            .and_then(|f| f.take(512).read_to_end(&mut buf))
+            .ok()?;
+        if buf.starts_with(b"PK\x03\x04") {
+            Some(ArchiveFormat::Zip)
+        } else if buf.starts_with(b"Rar!\x1a\x07") {
+            Some(ArchiveFormat::Rar)
+        } else if buf.starts_with(b"7z\xbc\xaf\x27\x1c") {
+            Some(ArchiveFormat::SevenZip)
+        } else if buf.starts_with(b"\x1f\x8b") {
+            Some(ArchiveFormat::TarGz)
+        } else if buf.get(257..262) == Some(b"ustar") {
+            Some(ArchiveFormat::Tar)
+        } else if matches!(buf.get(2..5), Some(b"-lh" | b"-lz")) {
+            Some(ArchiveFormat::Lzh)
+        } else {
+            None
+        }
+    }
+}
+
+/// names windows can't create files with, even with extensions
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// a name NTFS can store. trailing dots and spaces are dropped by windows
+fn sanitize_component(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim_end_matches(['.', ' ']);
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if RESERVED_NAMES.iter().any(|r| stem.eq_ignore_ascii_case(r)) {
+        format!("_{}", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+// "C:" is a drive on windows
+fn has_drive(name: &str) -> bool {
+    let b = name.as_bytes();
+    b.len() >= 2 && b[0].is_ascii_alphabetic() && b[1] == b':'
+}
+
+/// relative path of an archived file, empty for the root entry itself, e.g.) "./".
+/// error if the path is absolute or goes out of the dir, e.g.) "../x", "/x", "C:\\x"
+fn relative_path(name: &str) -> std::result::Result<PathBuf, &'static str> {
+    let name = name.replace('\\', "/");
+    if has_drive(&name) {
+        return Err("it is an absolute path");
+    }
+    let mut path = PathBuf::new();
+    for c in Path::new(&name).components() {
+        match c {
+            Component::Normal(s) => {
+                let s = sanitize_component(&s.to_string_lossy());
+                if s.is_empty() {
+                    return Err("it has no valid name");
+                }
+                path.push(s);
+            }
+            Component::CurDir => {}
+            Component::ParentDir => return Err("it escapes the destination"),
+            Component::RootDir | Component::Prefix(_) => return Err("it is an absolute path"),
+        }
+    }
+    Ok(path)
+}
+
+// the path to extract the file to, which must be in dest
+fn extract_path(archive: &Path, dest: &Path, name: &str) -> Option<PathBuf> {
+    match relative_path(name) {
+        Ok(path) if path.as_os_str().is_empty() => None,
+        Ok(path) => Some(dest.join(path)),
+        Err(reason) => {
+            warn!("skip {:?} in {:?}: {}", name, archive, reason);
+            None
+        }
+    }
+}
+
+/// zip reports checksum mismatches as other io errors while reading an entry,
+/// so they are marked as broken data here instead of failures of writing
+struct ZipEntryReader<R>(R);
+
+impl<R: Read> Read for ZipEntryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::Other => io::Error::new(io::ErrorKind::InvalidData, e),
+            _ => e,
+        })
+    }
+}
+
+fn extract_zip(
+    path: &Path,
+    dest: &Path,
+    name_encoding: Option<&'static Encoding>,
+    password: Option<&str>,
+) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    for i in 0..archive.len() {
+        let file = match password {
+            Some(password) => archive
+                .by_index_decrypt(i, password.as_bytes())?
+                .map_err(|e| ArchiveError::new(ErrorKind::Encrypted, e))?,
+            None => archive.by_index(i)?,
+        };
+
+        // UTF-8かSJISかなどを判定する
+        let name = encoding::decode(file.name_raw(), name_encoding).text;
+        let Some(t) = extract_path(path, dest, &name) else {
+            continue;
+        };
+        if file.is_dir() {
+            fs::create_dir_all(&t)?;
+            continue;
+        }
+
+        write_file(&t, &mut ZipEntryReader(file))?;
+    }
+    Ok(())
+}
+
+fn extract_rar(path: &Path, dest: &Path, password: Option<&str>) -> Result<()> {
+    let archive = match password {
+        Some(password) => Archive::with_password(path, password),
+        None => Archive::new(path),
+    };
+    let mut archive = archive.open_for_processing()?;
+    while let Some(header) = archive.read_header()? {
+        debug!(
+            "{} bytes: {}",
+            header.entry().unpacked_size,
+            header.entry().filename.to_string_lossy(),
+        );
+        let name = header.entry().filename.to_string_lossy().into_owned();
+        archive = match extract_path(path, dest, &name) {
+            Some(t) if header.entry().is_file() => {
+                if let Some(parent) = t.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                header.extract_to(t)?
+            }
+            _ => header.skip()?,
+        };
+    }
+    Ok(())
+}
+
+fn extract_7z(path: &Path, dest: &Path, password: Option<&str>) -> Result<()> {
+    let password = password.map_or(sevenz_rust::Password::empty(), |p| p.into());
+    let mut reader = sevenz_rust::SevenZReader::open(path, password)?;
+    reader.for_each_entries(|entry, data| {
+        match extract_path(path, dest, entry.name()) {
+            Some(t) if entry.is_directory() => fs::create_dir_all(t)?,
+            Some(t) => write_file(&t, data)?,
+            // files in a solid block are read in order
+            None => {
+                io::copy(data, &mut io::sink())?;
+            }
+        }
+        Ok(true)
+    })?;
+    Ok(())
+}
+
+// lha paths are raw bytes separated by 0xff, and are SJIS in most of the bms packages
+fn lzh_name(header: &delharc::LhaHeader, name_encoding: Option<&'static Encoding>) -> String {
+    let mut dir: &[u8] = &[];
+    let mut filename: &[u8] = &header.filename;
+    for extra in header.iter_extra() {
+        match extra {
+            [0x01, data @ ..] => filename = data,
+            [0x02, data @ ..] => dir = data,
+            _ => {}
+        }
+    }
+    let mut raw = dir.to_vec();
+    if !raw.is_empty() {
+        raw.push(b'/');
+    }
+    raw.extend_from_slice(filename);
+    let raw: Vec<u8> = raw
+        .into_iter()
+        .map(|c| if c == 0xff { b'/' } else { c })
+        .collect();
+    encoding::decode(&raw, name_encoding).text
+}
+
+fn extract_lzh(path: &Path, dest: &Path, name_encoding: Option<&'static Encoding>) -> Result<()> {
+    let mut reader = delharc::parse_file(path)?;
+    loop {
+        let header = reader.header();
+        let name = lzh_name(header, name_encoding);
+        debug!("{} bytes: {}", header.original_size, name);
+        match extract_path(path, dest, &name) {
+            Some(t) if header.is_directory() => fs::create_dir_all(t)?,
+            Some(t) if reader.is_decoder_supported() => {
+                write_file(&t, &mut reader)?;
+                reader.crc_check().map_err(io::Error::from)?;
+            }
+            Some(_) => {
+                return Err(ArchiveError::new(
+                    ErrorKind::Unsupported,
+                    format!("compression of {:?} is not supported", name),
+                ))
+            }
+            None => {}
+        }
+        if !reader.next_file().map_err(io::Error::from)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn extract_tar<R: Read>(
+    path: &Path,
+    dest: &Path,
+    name_encoding: Option<&'static Encoding>,
+    reader: R,
+) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = encoding::decode(&entry.path_bytes(), name_encoding).text;
+        let kind = entry.header().entry_type();
+        match extract_path(path, dest, &name) {
+            Some(t) if kind.is_dir() => fs::create_dir_all(t)?,
+            // links may point out of dest
+            Some(t) if kind.is_file() => write_file(&t, &mut entry)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_file(path: &Path, reader: &mut dyn Read) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut output = fs::File::create(path)?;
+    io::copy(reader, &mut output)?;
+    Ok(())
+}
+
+fn extract_with(
+    path: &Path,
+    dest: &Path,
+    format: ArchiveFormat,
+    name_encoding: Option<&'static Encoding>,
+    password: Option<&str>,
+) -> Result<()> {
+    match format {
+        ArchiveFormat::Zip => extract_zip(path, dest, name_encoding, password),
+        ArchiveFormat::Rar => extract_rar(path, dest, password),
+        ArchiveFormat::SevenZip => extract_7z(path, dest, password),
+        ArchiveFormat::Lzh => extract_lzh(path, dest, name_encoding),
+        ArchiveFormat::Tar => extract_tar(path, dest, name_encoding, fs::File::open(path)?),
+        ArchiveFormat::TarGz => extract_tar(
+            path,
+            dest,
+            name_encoding,
+            flate2::read::GzDecoder::new(fs::File::open(path)?),
+        ),
+    }
+}
+
+/// extract the archive into dest, keeping its structure.
+/// the passwords are tried in order if it's encrypted
+pub fn extract(
+    path: &Path,
+    dest: &Path,
+    format: ArchiveFormat,
+    name_encoding: Option<&'static Encoding>,
+    passwords: &[String],
+) -> Result<()> {
+    let result = extract_with(path, dest, format, name_encoding, None);
+    match &result {
+        Err(e) if e.kind == ErrorKind::Encrypted && !passwords.is_empty() => {}
+        _ => return result,
+    }
+    for (i, password) in passwords.iter().enumerate() {
+        debug!("trying password #{} for {:?}", i + 1, path);
+        // files extracted with a wrong password are broken
+        if dest.exists() {
+            fs::remove_dir_all(dest)?;
+        }
+        fs::create_dir_all(dest)?;
+        match extract_with(path, dest, format, name_encoding, Some(password)) {
+            Ok(()) => {
+                info!("extracted {:?} with password #{}", path, i + 1);
+                return Ok(());
+            }
+            // a wrong password may look like broken data
+            Err(e) if matches!(e.kind, ErrorKind::Encrypted | ErrorKind::Corrupt) => {
+                debug!("password #{} failed: {}", i + 1, e)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(ArchiveError::new(
+        ErrorKind::Encrypted,
+        format!("none of {} passwords matched", passwords.len()),
+    ))
+}
@@ -0,0 +1,162 @@
+use crate::library::IndexedChart;
+use crate::sounds::{referenced_sounds, SoundFiles};
+use crate::utils::{normalize_name, remove_difficulty, remove_obj};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// a folder having at least this ratio of the sounds is regarded as the base song
+pub const MIN_SOUND_COVERAGE: f64 = 0.5;
+/// songs with other titles need almost all the sounds, as keysound names like kick.wav are common
+const MIN_FALLBACK_SOUND_COVERAGE: f64 = 0.95;
+
+struct Candidate {
+    folder: PathBuf,
+    chart: PathBuf,
+    artist: String,
+}
+
+impl Candidate {
+    fn artist_matches(&self, artist: &str) -> bool {
+        artist.is_empty()
+            || self.artist.is_empty()
+            || self.artist.contains(artist)
+            || artist.contains(&self.artist)
+    }
+}
+
+fn title_key(title: &str) -> String {
+    normalize_name(&remove_difficulty(title))
+}
+
+fn artist_key(artist: &str) -> String {
+    normalize_name(&remove_obj(artist))
+}
+
+/// song folders in the library, to find the base song of a sabun (diff chart)
+pub struct BaseFolders {
+    candidates: Vec<Candidate>,
+    by_title: HashMap<String, Vec<usize>>,
+}
+
+impl BaseFolders {
+    pub fn new(charts: &[IndexedChart]) -> Self {
+        let mut candidates: Vec<Candidate> = vec![];
+        let mut by_title: HashMap<String, Vec<usize>> = HashMap::new();
+        for c in charts {
+            let Some(title) = &c.title else { continue };
+            let indices = by_title.entry(title_key(title)).or_default();
+            if indices.iter().all(|i| candidates[*i].folder != c.folder) {
+                indices.push(candidates.len());
+                candidates.push(Candidate {
+                    folder: c.folder.clone(),
+                    chart: c.path.clone(),
+                    artist: artist_key(c.artist.as_deref().unwrap_or("")),
+                });
+            }
+        }
+        BaseFolders {
+            candidates,
+            by_title,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    fn same_title(&self, title: &str, artist: &str) -> Vec<&Candidate> {
+        let artist = artist_key(artist);
+        self.by_title
+            .get(&title_key(title))
+            .into_iter()
+            .flatten()
+            .map(|i| &self.candidates[*i])
+            .filter(|c| c.artist_matches(&artist))
+            .collect()
+    }
+
+    /// folder of the song with the same title and artist, which has the sounds of its charts
+    pub fn find(&self, title: &str, artist: &str) -> Option<PathBuf> {
+        self.same_title(title, artist)
+            .into_iter()
+            .flat_map(|c| {
+                let sounds = referenced_sounds(&c.chart).ok()?;
+                let coverage = SoundFiles::new(&c.folder).coverage(&sounds);
+                debug!("{:?} has {:.0}% of the sounds", c.folder, coverage * 100.0);
+                (coverage >= MIN_SOUND_COVERAGE).then_some((coverage, &c.folder))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, folder)| folder.clone())
+    }
+
+    /// folder having the most sounds referred by the chart, and the number of them.
+    /// songs of the same artist are searched if no song has the same title,
+    /// only when one of them has almost all the sounds.
+    pub fn find_by_sounds(
+        &self,
+        title: &str,
+        artist: &str,
+        sounds: &[String],
+    ) -> Option<(&Path, usize)> {
+        let candidates = self.same_title(title, artist);
+        if !candidates.is_empty() {
+            return by_sounds(candidates, sounds, MIN_SOUND_COVERAGE)
+                .first()
+                .copied();
+        }
+
+        let artist = artist_key(artist);
+        if artist.is_empty() {
+            return None;
+        }
+        let candidates: Vec<&Candidate> = self
+            .candidates
+            .iter()
+            .filter(|c| !c.artist.is_empty() && c.artist_matches(&artist))
+            .collect();
+        let found = match by_sounds(candidates, sounds, MIN_FALLBACK_SOUND_COVERAGE).as_slice() {
+            [] => return None,
+            // other songs can't be told apart
+            [first, second, ..] if first.1 == second.1 => {
+                warn!(
+                    "both {:?} and {:?} have the sounds of {:?}",
+                    first.0, second.0, title
+                );
+                return None;
+            }
+            [first, ..] => *first,
+        };
+        info!(
+            "no song titled {:?}. {:?} of the same artist has {} / {} sounds",
+            title,
+            found.0,
+            found.1,
+            sounds.len()
+        );
+        Some(found)
+    }
+}
+
+// folders having enough sounds, the most first
+fn by_sounds<'a>(
+    candidates: Vec<&'a Candidate>,
+    sounds: &[String],
+    min_coverage: f64,
+) -> Vec<(&'a Path, usize)> {
+    if sounds.is_empty() {
+        return vec![];
+    }
+    let mut found: Vec<(&Path, usize)> = candidates
+        .into_iter()
+        .map(|c| {
+            let files = SoundFiles::new(&c.folder);
+            let resolved = sounds.iter().filter(|s| files.contains(s)).count();
+            debug!("{:?} has {} / {} sounds", c.folder, resolved, sounds.len());
+            (c.folder.as_path(), resolved)
+        })
+        .filter(|(_, resolved)| *resolved as f64 / sounds.len() as f64 >= min_coverage)
+        .collect();
+    found.sort_by_key(|(_, resolved)| std::cmp::Reverse(*resolved));
+    found
+}
@@ -0,0 +1,602 @@
+use crate::encoding;
+use crate::errors::Result;
+use log::debug;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// two-character object id used by #WAVxx, #BMPxx and channel data. e.g.) "0A"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjId([u8; 2]);
+
+impl ObjId {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.as_bytes() {
+            [a, b] if a.is_ascii_alphanumeric() && b.is_ascii_alphanumeric() => {
+                Some(ObjId([a.to_ascii_uppercase(), b.to_ascii_uppercase()]))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == [b'0', b'0']
+    }
+
+    /// value of the id as a hexadecimal number (used by channel 03)
+    pub fn as_hex(&self) -> Option<u8> {
+        u8::from_str_radix(std::str::from_utf8(&self.0).ok()?, 16).ok()
+    }
+}
+
+impl fmt::Display for ObjId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.0[0] as char, self.0[1] as char)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BmsHeader {
+    pub player: Option<u8>,
+    pub genre: Option<String>,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub artist: Option<String>,
+    pub subartist: Option<String>,
+    pub playlevel: Option<String>,
+    pub difficulty: Option<u8>,
+    pub rank: Option<u8>,
+    pub total: Option<f64>,
+    pub bpm: Option<f64>,
+    pub lntype: Option<u8>,
+    pub lnobj: Option<ObjId>,
+    /// headers without a dedicated field (#STAGEFILE, #BANNER, ...). keys are upper case
+    pub others: BTreeMap<String, String>,
+}
+
+/// one `#mmmcc:data` line
+#[derive(Debug, Clone)]
+pub struct ChannelData {
+    pub measure: u32,
+    /// upper-cased two-character channel. e.g.) "01", "11", "5A"
+    pub channel: String,
+    pub objects: Vec<ObjId>,
+}
+
+impl ChannelData {
+    fn is_playable(&self) -> bool {
+        matches!(self.channel.as_bytes(), [b'1' | b'2', b'1'..=b'9'])
+    }
+
+    fn is_long_note(&self) -> bool {
+        matches!(self.channel.as_bytes(), [b'5' | b'6', b'1'..=b'9'])
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BmsChart {
+    pub header: BmsHeader,
+    pub wav: BTreeMap<ObjId, String>,
+    pub bmp: BTreeMap<ObjId, String>,
+    /// extended bpm definitions (#BPMxx / #EXBPMxx)
+    pub bpm_defs: BTreeMap<ObjId, f64>,
+    pub stop_defs: BTreeMap<ObjId, f64>,
+    /// measure length ratio (channel 02)
+    pub measure_lengths: BTreeMap<u32, f64>,
+    pub channels: Vec<ChannelData>,
+}
+
+/// bpm range and play length of a chart
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+    pub length_secs: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum TimingEvent {
+    Bpm(f64),
+    /// length of the stop in beats
+    Stop(f64),
+}
+
+/// integrate the time from beat 0 to the last beat. events are (beat, event)
+pub fn timing_from_events(
+    init_bpm: f64,
+    mut events: Vec<(f64, TimingEvent)>,
+    last_beat: f64,
+) -> Option<Timing> {
+    if init_bpm <= 0.0 {
+        return None;
+    }
+    // bpm changes are applied before stops on the same beat
+    events.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut bpm = init_bpm;
+    let (mut min_bpm, mut max_bpm) = (init_bpm, init_bpm);
+    let mut secs = 0.0;
+    let mut pos = 0.0;
+    for (beat, event) in events.into_iter().filter(|(b, _)| *b <= last_beat) {
+        secs += (beat - pos) * 60.0 / bpm;
+        pos = beat;
+        match event {
+            TimingEvent::Bpm(v) if v > 0.0 => {
+                bpm = v;
+                min_bpm = min_bpm.min(v);
+                max_bpm = max_bpm.max(v);
+            }
+            TimingEvent::Stop(beats) => secs += beats * 60.0 / bpm,
+            _ => {}
+        }
+    }
+    secs += (last_beat - pos) * 60.0 / bpm;
+
+    Some(Timing {
+        min_bpm,
+        max_bpm,
+        length_secs: secs,
+    })
+}
+
+/// which #IF branches are read while parsing
+#[derive(Debug, Clone)]
+pub enum Branches {
+    /// every line is read regardless of #RANDOM / #IF blocks
+    All,
+    /// value of each #RANDOM, in order of appearance in the file.
+    /// missing values are treated as 1. #SETRANDOM always uses its own value.
+    Select(Vec<u32>),
+}
+
+impl Default for Branches {
+    fn default() -> Self {
+        Branches::Select(vec![])
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Cond {
+    taken: bool,
+    matched: bool,
+}
+
+#[derive(Debug)]
+struct RandomFrame {
+    value: Option<u32>,
+    conds: Vec<Cond>,
+}
+
+/// tracks nested #RANDOM / #IF blocks
+struct ControlFlow<'a> {
+    branches: &'a Branches,
+    frames: Vec<RandomFrame>,
+    random_count: usize,
+}
+
+impl<'a> ControlFlow<'a> {
+    fn new(branches: &'a Branches) -> Self {
+        ControlFlow {
+            branches,
+            frames: vec![],
+            random_count: 0,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.frames.iter().flat_map(|f| &f.conds).all(|c| c.taken)
+    }
+
+    fn push_random(&mut self, fixed: Option<u32>) {
+        let value = match self.branches {
+            Branches::All => None,
+            Branches::Select(values) => {
+                fixed.or(Some(values.get(self.random_count).copied().unwrap_or(1)))
+            }
+        };
+        self.random_count += 1;
+
+        // #ENDRANDOM is often omitted. a #RANDOM outside of any #IF replaces the current one
+        if self.frames.last().is_some_and(|f| f.conds.is_empty()) {
+            self.frames.pop();
+        }
+        self.frames.push(RandomFrame {
+            value,
+            conds: vec![],
+        });
+    }
+
+    // #RANDOM inside an #IF without #ENDRANDOM ends with the #IF
+    fn close_inner_randoms(&mut self) {
+        if let Some(i) = self.frames.iter().rposition(|f| !f.conds.is_empty()) {
+            self.frames.truncate(i + 1);
+        }
+    }
+
+    fn matches(&self, v: Option<u32>) -> bool {
+        match self.branches {
+            Branches::All => true,
+            Branches::Select(_) => {
+                let value = self.frames.last().and_then(|f| f.value);
+                value.is_some() && value == v
+            }
+        }
+    }
+
+    fn end_if(&mut self) {
+        self.close_inner_randoms();
+        self.frames.last_mut().and_then(|f| f.conds.pop());
+    }
+
+    /// returns true if the line is a control flow command
+    fn process(&mut self, line: &str) -> bool {
+        let Some((key, value)) = split_header(line) else {
+            return false;
+        };
+        let arg: Option<u32> = value.parse().ok();
+        match key.as_str() {
+            "RANDOM" => self.push_random(None),
+            "SETRANDOM" => self.push_random(arg),
+            "ENDRANDOM" => {
+                self.frames.pop();
+            }
+            "IF" => {
+                let taken = self.matches(arg);
+                if self.frames.is_empty() {
+                    // #IF without #RANDOM
+                    self.frames.push(RandomFrame {
+                        value: None,
+                        conds: vec![],
+                    });
+                }
+                if let Some(f) = self.frames.last_mut() {
+                    f.conds.push(Cond {
+                        taken,
+                        matched: taken,
+                    });
+                }
+            }
+            "ELSEIF" => {
+                self.close_inner_randoms();
+                let taken = self.matches(arg);
+                let all = matches!(self.branches, Branches::All);
+                if let Some(c) = self.frames.last_mut().and_then(|f| f.conds.last_mut()) {
+                    c.taken = all || (!c.matched && taken);
+                    c.matched |= c.taken;
+                }
+            }
+            "ELSE" => {
+                self.close_inner_randoms();
+                let all = matches!(self.branches, Branches::All);
+                if let Some(c) = self.frames.last_mut().and_then(|f| f.conds.last_mut()) {
+                    c.taken = all || !c.matched;
+                    c.matched = true;
+                }
+            }
+            "ENDIF" => self.end_if(),
+            "END" if value.eq_ignore_ascii_case("IF") => self.end_if(),
+            _ => return false,
+        }
+        true
+    }
+}
+
+fn channel_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^#(\d{3})([0-9A-Za-z]{2}):(.*)$").unwrap())
+}
+
+// split "#KEY value" into ("KEY", "value")
+fn split_header(line: &str) -> Option<(String, &str)> {
+    let body = line.strip_prefix('#')?;
+    let (key, value) = match body.find(|c: char| c.is_whitespace()) {
+        Some(i) => (&body[..i], body[i..].trim()),
+        None => (body, ""),
+    };
+    if key.is_empty() {
+        return None;
+    }
+    Some((key.to_ascii_uppercase(), value))
+}
+
+// "#WAV0A" -> Some(0A) when the key has the given prefix
+fn indexed_key(key: &str, prefix: &str) -> Option<ObjId> {
+    key.strip_prefix(prefix).and_then(ObjId::parse)
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+impl BmsChart {
+    pub fn parse(text: &str, branches: &Branches) -> Self {
+        let mut chart = BmsChart::default();
+        let mut flow = ControlFlow::new(branches);
+        for line in text.lines() {
+            let line = line.trim();
+            if !line.starts_with('#') || flow.process(line) {
+                continue;
+            }
+            if flow.is_active() {
+                chart.parse_line(line);
+            }
+        }
+        chart
+    }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Self::from_path_with(path, &Branches::default())
+    }
+
+    pub fn from_path_with(path: &Path, branches: &Branches) -> Result<Self> {
+        let text = encoding::read_file(path)?.text;
+        let chart = Self::parse(&text, branches);
+        debug!(
+            "parsed {:?}: {:?}, {} notes",
+            path,
+            chart.header,
+            chart.total_notes()
+        );
+        Ok(chart)
+    }
+
+    fn parse_line(&mut self, line: &str) {
+        if let Some(caps) = channel_regex().captures(line) {
+            let measure: u32 = caps[1].parse().unwrap_or(0);
+            let channel = caps[2].to_ascii_uppercase();
+            let data = caps[3].trim();
+            if channel == "02" {
+                if let Ok(len) = data.parse() {
+                    self.measure_lengths.insert(measure, len);
+                }
+                return;
+            }
+            let objects = data
+                .as_bytes()
+                .chunks_exact(2)
+                .flat_map(|c| std::str::from_utf8(c).ok().and_then(ObjId::parse))
+                .collect();
+            self.channels.push(ChannelData {
+                measure,
+                channel,
+                objects,
+            });
+            return;
+        }
+
+        let Some((key, value)) = split_header(line) else {
+            return;
+        };
+        let h = &mut self.header;
+        match key.as_str() {
+            "PLAYER" => h.player = value.parse().ok(),
+            "GENRE" => h.genre = non_empty(value),
+            "TITLE" => h.title = non_empty(value),
+            "SUBTITLE" => h.subtitle = non_empty(value),
+            "ARTIST" => h.artist = non_empty(value),
+            "SUBARTIST" => h.subartist = non_empty(value),
+            "PLAYLEVEL" => h.playlevel = non_empty(value),
+            "DIFFICULTY" => h.difficulty = value.parse().ok(),
+            "RANK" => h.rank = value.parse().ok(),
+            "TOTAL" => h.total = value.parse().ok(),
+            "BPM" => h.bpm = value.parse().ok(),
+            "LNTYPE" => h.lntype = value.parse().ok(),
+            "LNOBJ" => h.lnobj = ObjId::parse(value),
+            k => {
+                if let Some(id) = indexed_key(k, "WAV") {
+                    self.wav.insert(id, value.to_owned());
+                } else if let Some(id) = indexed_key(k, "BMP") {
+                    self.bmp.insert(id, value.to_owned());
+                } else if let Some(id) = indexed_key(k, "EXBPM").or(indexed_key(k, "BPM")) {
+                    if let Ok(v) = value.parse() {
+                        self.bpm_defs.insert(id, v);
+                    }
+                } else if let Some(id) = indexed_key(k, "STOP") {
+                    if let Ok(v) = value.parse() {
+                        self.stop_defs.insert(id, v);
+                    }
+                } else {
+                    h.others.insert(key.clone(), value.to_owned());
+                }
+            }
+        }
+    }
+
+    /// number of notes a player has to hit. a long note counts as one note.
+    pub fn total_notes(&self) -> usize {
+        let lnobj = self.header.lnobj;
+        let normal: usize = self
+            .channels
+            .iter()
+            .filter(|c| c.is_playable())
+            .flat_map(|c| &c.objects)
+            .filter(|o| !o.is_empty() && Some(**o) != lnobj)
+            .count();
+        // LNTYPE 1: the start and the end of a long note are written in pairs
+        let long: usize = self
+            .channels
+            .iter()
+            .filter(|c| c.is_long_note())
+            .flat_map(|c| &c.objects)
+            .filter(|o| !o.is_empty())
+            .count();
+        normal + long / 2
+    }
+
+    /// bpm range, and the time until the last object
+    pub fn timing(&self) -> Option<Timing> {
+        let ratio = |m: u32| self.measure_lengths.get(&m).copied().unwrap_or(1.0);
+        let last_measure = self.channels.iter().map(|c| c.measure).max().unwrap_or(0);
+        let measure_start: Vec<f64> = (0..=last_measure)
+            .scan(0.0, |beat, m| {
+                let start = *beat;
+                *beat += ratio(m) * 4.0;
+                Some(start)
+            })
+            .collect();
+
+        let mut events = vec![];
+        let mut last_beat: f64 = 0.0;
+        for c in &self.channels {
+            let n = c.objects.len() as f64;
+            for (i, o) in c.objects.iter().enumerate().filter(|(_, o)| !o.is_empty()) {
+                let beat =
+                    measure_start[c.measure as usize] + ratio(c.measure) * 4.0 * i as f64 / n;
+                last_beat = last_beat.max(beat);
+                let event = match c.channel.as_str() {
+                    "03" => o.as_hex().map(|v| TimingEvent::Bpm(v as f64)),
+                    "08" => self.bpm_defs.get(o).map(|v| TimingEvent::Bpm(*v)),
+                    // 192 per a measure of 4/4
+                    "09" => self.stop_defs.get(o).map(|v| TimingEvent::Stop(v / 48.0)),
+                    _ => None,
+                };
+                if let Some(e) = event {
+                    events.push((beat, e));
+                }
+            }
+        }
+
+        timing_from_events(self.header.bpm?, events, last_beat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str], values: &[u32]) -> BmsHeader {
+        BmsChart::parse(&lines.join("\n"), &Branches::Select(values.to_vec())).header
+    }
+
+    #[test]
+    fn nested_random() {
+        let lines = [
+            "#RANDOM 2",
+            "#IF 2",
+            "#RANDOM 2",
+            "#IF 1",
+            "#TITLE a",
+            "#ELSE",
+            "#TITLE b",
+            "#ENDIF",
+            "#ENDRANDOM",
+            "#ARTIST x",
+            "#ENDIF",
+            "#ENDRANDOM",
+            "#GENRE top",
+        ];
+        let header = parse(&lines, &[2, 2]);
+        assert_eq!(header.title.as_deref(), Some("b"));
+        assert_eq!(header.artist.as_deref(), Some("x"));
+        assert_eq!(header.genre.as_deref(), Some("top"));
+
+        let header = parse(&lines, &[1, 1]);
+        assert_eq!(header.title, None);
+        assert_eq!(header.artist, None);
+        assert_eq!(header.genre.as_deref(), Some("top"));
+    }
+
+    #[test]
+    fn nested_random_without_endrandom() {
+        let lines = [
+            "#RANDOM 2",
+            "#IF 1",
+            "#RANDOM 3",
+            "#IF 3",
+            "#ARTIST z",
+            "#ENDIF",
+            "#ENDIF",
+            "#TITLE top",
+        ];
+        let header = parse(&lines, &[2, 1]);
+        assert_eq!(header.title.as_deref(), Some("top"));
+        assert_eq!(header.artist, None);
+
+        let header = parse(&lines, &[1, 3]);
+        assert_eq!(header.title.as_deref(), Some("top"));
+        assert_eq!(header.artist.as_deref(), Some("z"));
+    }
+
+    #[test]
+    fn else_after_nested_random_without_endrandom() {
+        let lines = [
+            "#RANDOM 2",
+            "#IF 1",
+            "#RANDOM 2",
+            "#IF 2",
+            "#ARTIST z",
+            "#ENDIF",
+            "#ELSE",
+            "#TITLE other",
+            "#END IF",
+        ];
+        let header = parse(&lines, &[2]);
+        assert_eq!(header.title.as_deref(), Some("other"));
+        assert_eq!(header.artist, None);
+
+        let header = parse(&lines, &[1, 2]);
+        assert_eq!(header.title, None);
+        assert_eq!(header.artist.as_deref(), Some("z"));
+    }
+
+    #[test]
+    fn sequential_random_without_endrandom() {
+        let lines = [
+            "#RANDOM 2",
+            "#IF 1",
+            "#TITLE a",
+            "#ENDIF",
+            "#RANDOM 2",
+            "#IF 2",
+            "#ARTIST b",
+            "#ENDIF",
+        ];
+        let header = parse(&lines, &[1, 2]);
+        assert_eq!(header.title.as_deref(), Some("a"));
+        assert_eq!(header.artist.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn setrandom() {
+        let lines = ["#SETRANDOM 3", "#IF 3", "#TITLE s", "#ENDIF"];
+        assert_eq!(parse(&lines, &[1]).title.as_deref(), Some("s"));
+    }
+
+    #[test]
+    fn elseif_and_else() {
+        let lines = [
+            "#RANDOM 3",
+            "#IF 1",
+            "#TITLE one",
+            "#ELSEIF 2",
+            "#TITLE two",
+            "#ELSE",
+            "#TITLE other",
+            "#ENDIF",
+        ];
+        assert_eq!(parse(&lines, &[1]).title.as_deref(), Some("one"));
+        assert_eq!(parse(&lines, &[2]).title.as_deref(), Some("two"));
+        assert_eq!(parse(&lines, &[3]).title.as_deref(), Some("other"));
+    }
+
+    #[test]
+    fn all_branches() {
+        let lines = [
+            "#RANDOM 2",
+            "#IF 1",
+            "#TITLE a",
+            "#ELSE",
+            "#ARTIST b",
+            "#ENDIF",
+        ];
+        let header = BmsChart::parse(&lines.join("\n"), &Branches::All).header;
+        assert_eq!(header.title.as_deref(), Some("a"));
+        assert_eq!(header.artist.as_deref(), Some("b"));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::bms::{timing_from_events, Timing, TimingEvent};
+use crate::encoding;
+use crate::errors::Result;
+use log::debug;
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct BmsonInfo {
+    pub title: String,
+    pub subtitle: String,
+    pub artist: String,
+    pub subartists: Vec<String>,
+    pub genre: String,
+    pub level: i64,
+    pub init_bpm: f64,
+    /// pulses per a beat
+    pub resolution: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct BmsonNote {
+    /// lane. 0 (or missing) means a BGM note
+    pub x: Option<u32>,
+    pub y: u64,
+    /// length of a long note
+    pub l: u64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct BpmEvent {
+    pub y: u64,
+    pub bpm: f64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct StopEvent {
+    pub y: u64,
+    pub duration: u64,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SoundChannel {
+    /// sound file name
+    pub name: String,
+    pub notes: Vec<BmsonNote>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Bmson {
+    pub info: BmsonInfo,
+    pub bpm_events: Vec<BpmEvent>,
+    pub stop_events: Vec<StopEvent>,
+    pub sound_channels: Vec<SoundChannel>,
+}
+
+impl Bmson {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let text = encoding::read_file(path)?.text;
+        let bmson: Bmson = serde_json::from_str(&text)?;
+        debug!(
+            "parsed {:?}: {:?}, {} notes",
+            path,
+            bmson.info,
+            bmson.total_notes()
+        );
+        Ok(bmson)
+    }
+
+    pub fn total_notes(&self) -> usize {
+        self.sound_channels
+            .iter()
+            .flat_map(|c| &c.notes)
+            .filter(|n| n.x.is_some_and(|x| x > 0))
+            .count()
+    }
+
+    pub fn timing(&self) -> Option<Timing> {
+        let resolution = self.info.resolution.filter(|r| *r > 0).unwrap_or(240) as f64;
+        let beat = |y: u64| y as f64 / resolution;
+        let events = self
+            .bpm_events
+            .iter()
+            .map(|e| (beat(e.y), TimingEvent::Bpm(e.bpm)))
+            .chain(
+                self.stop_events
+                    .iter()
+                    .map(|e| (beat(e.y), TimingEvent::Stop(beat(e.duration)))),
+            )
+            .collect();
+        let last_beat = self
+            .sound_channels
+            .iter()
+            .flat_map(|c| &c.notes)
+            .map(|n| beat(n.y + n.l))
+            .fold(0.0, f64::max);
+        timing_from_events(self.info.init_bpm, events, last_beat)
+    }
+}
@@ -1,6 +1,8 @@
 use crate::errors::Result;
+use crate::hash_cache::{file_stat, CachedHash, HashCache};
 use jwalk::WalkDir;
-use log::{debug, info};
+use log::{debug, info, warn};
+use md5::Md5;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -11,7 +13,7 @@ use rayon::prelude::*;
 
 type ChartHashMap = HashMap<String, Vec<PathBuf>>;
 
-pub const BMS_EXTENSIONS: &[&str] = &["bms", "bml", "bme", "pms"];
+pub const BMS_EXTENSIONS: &[&str] = &["bms", "bml", "bme", "pms", "bmson"];
 
 pub fn filter_bms_files(path: &Path) -> bool {
     BMS_EXTENSIONS.iter().any(|e| {
@@ -19,6 +21,10 @@ pub fn filter_bms_files(path: &Path) -> bool {
     })
 }
 
+pub fn is_bmson(path: &Path) -> bool {
+    path.extension().map(|ext| ext.to_ascii_lowercase()) == Some(std::ffi::OsString::from("bmson"))
+}
+
 fn charts_traverse(dir: &Path) -> Vec<PathBuf> {
     WalkDir::new(dir)
         .into_iter()
@@ -43,59 +49,185 @@ fn filter_subdir(dir_paths: HashSet<&Path>) -> Result<HashSet<&Path>> {
     Ok(result)
 }
 
+// feeds both digests in a single pass over the file
+struct DualHasher {
+    sha256: Sha256,
+    md5: Md5,
+}
+
+impl io::Write for DualHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sha256.update(buf);
+        self.md5.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 pub struct ChartHashes {
     hash_with_dir: ChartHashMap,
+    md5_with_dir: ChartHashMap,
+    entries: Vec<(PathBuf, CachedHash)>,
+}
+
+fn hash_file(path: &Path) -> io::Result<(String, String)> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = DualHasher {
+        sha256: Sha256::new(),
+        md5: Md5::new(),
+    };
+    io::copy(&mut file, &mut hasher)?;
+    let hash_string = format!("{:x}", hasher.sha256.finalize());
+    let md5_string = format!("{:x}", hasher.md5.finalize());
+    debug!(
+        "Binary hash of {:?} is {} (md5: {})",
+        path, hash_string, md5_string
+    );
+    Ok((hash_string, md5_string))
 }
 
 impl ChartHashes {
-    pub fn new(dir: &Path) -> Result<Self> {
+    pub fn new(dir: &Path, cache: &HashCache) -> Result<Self> {
         info!("looking charts up....");
         let charts = charts_traverse(dir);
         info!("found {:?} charts", charts.len());
 
+        let cached = cache.load(dir).unwrap_or_else(|e| {
+            warn!("failed to load hash cache: {}", e);
+            HashMap::new()
+        });
+
+        let stats: Vec<(&Path, u64, i64)> = charts
+            .par_iter()
+            .filter(|path| !path.starts_with("$RECYCLE.BIN") && !path.starts_with("."))
+            .flat_map(|path| file_stat(path).map(|(size, mtime)| (path.as_path(), size, mtime)))
+            .collect();
+
+        // unchanged files are never rehashed
+        let (hits, misses): (Vec<_>, Vec<_>) = stats.iter().partition(|(path, size, mtime)| {
+            cached
+                .get(*path)
+                .is_some_and(|c| c.size == *size && c.mtime == *mtime)
+        });
+        info!("{:?} charts are cached", hits.len());
+
+        let hit_paths: Vec<&Path> = hits.iter().map(|(path, _, _)| *path).collect();
+        let samples = cache.samples(&hit_paths);
+        let broken: HashSet<&Path> = samples
+            .par_iter()
+            .filter(|path| {
+                let c = &cached[**path];
+                match hash_file(path) {
+                    Ok((sha256, md5)) => sha256 != c.sha256 || md5 != c.md5,
+                    Err(_) => true,
+                }
+            })
+            .copied()
+            .collect();
+        if !samples.is_empty() {
+            info!(
+                "verified {:?} cached charts, {:?} mismatched",
+                samples.len(),
+                broken.len()
+            );
+        }
+        broken
+            .iter()
+            .for_each(|path| warn!("hash cache is stale: {:?}", path));
+
         // calculate hashes parallely
-        let chart_with_hashes: Vec<(String, &Path)>  = charts
-        .par_iter()
-        .filter(|path| !path.starts_with("$RECYCLE.BIN") && !path.starts_with("."))
-        .map(|path| {
-            let with_hash = || -> std::result::Result<(String, &Path), Box<dyn std::error::Error + Send + Sync>> {
-                let mut file = fs::File::open(path)?;
-                let mut hasher = Sha256::new();
-                io::copy(&mut file, &mut hasher)?;
-                let hash = hasher.finalize();
-                let hash_string = format!("{:x}", hash);
-                debug!("Binary hash of {:?} is {}", path, hash_string);
-                Ok((hash_string, path))
-            };
-            with_hash()
-        })
-        .flatten()
-        .collect::<Vec<(String, &Path)>>();
+        let fresh: Vec<(PathBuf, CachedHash)> = misses
+            .par_iter()
+            .chain(hits.par_iter().filter(|(path, _, _)| broken.contains(path)))
+            .flat_map(|(path, size, mtime)| {
+                hash_file(path).map(|(sha256, md5)| {
+                    (
+                        path.to_path_buf(),
+                        CachedHash {
+                            size: *size,
+                            mtime: *mtime,
+                            sha256,
+                            md5,
+                        },
+                    )
+                })
+            })
+            .collect();
 
-        info!("hashed {:?} charts", chart_with_hashes.len());
+        info!("hashed {:?} charts", fresh.len());
 
+        let found: HashSet<&Path> = stats.iter().map(|(path, _, _)| *path).collect();
+        let removed: Vec<PathBuf> = cached
+            .keys()
+            .filter(|path| !found.contains(path.as_path()))
+            .cloned()
+            .collect();
+        cache
+            .store(&fresh, &removed)
+            .unwrap_or_else(|e| warn!("failed to store hash cache: {}", e));
+
+        let chart_with_hashes: Vec<(PathBuf, CachedHash)> = hits
+            .iter()
+            .filter(|(path, _, _)| !broken.contains(path))
+            .map(|(path, _, _)| (path.to_path_buf(), cached[*path].clone()))
+            .chain(fresh)
+            .collect();
+
+        let chart_hashes = Self::from_entries(chart_with_hashes);
+        info!("different {:?} charts", chart_hashes.hash_with_dir.len());
+
+        Ok(chart_hashes)
+    }
+
+    pub fn from_entries(entries: Vec<(PathBuf, CachedHash)>) -> Self {
         let mut hash_with_dir = HashMap::new();
-        chart_with_hashes.into_iter().for_each(|(hash, path)| {
+        let mut md5_with_dir = HashMap::new();
+        entries.iter().for_each(|(path, c)| {
             hash_with_dir
-                .entry(hash)
+                .entry(c.sha256.clone())
+                .or_insert(vec![])
+                .push(path.to_path_buf());
+            md5_with_dir
+                .entry(c.md5.clone())
                 .or_insert(vec![])
                 .push(path.to_path_buf());
         });
 
-        info!("different {:?} charts", hash_with_dir.len());
+        ChartHashes {
+            hash_with_dir,
+            md5_with_dir,
+            entries,
+        }
+    }
 
-        Ok(ChartHashes { hash_with_dir })
+    pub fn entries(&self) -> &[(PathBuf, CachedHash)] {
+        &self.entries
     }
 
     pub fn hashes(&self) -> &ChartHashMap {
         &self.hash_with_dir
     }
 
+    /// look a chart up by sha256, or by md5 if sha256 is empty or unknown
+    pub fn find(&self, sha256: &str, md5: &str) -> Option<&Vec<PathBuf>> {
+        let by_sha256 = match sha256 {
+            "" => None,
+            h => self.hash_with_dir.get(&h.to_ascii_lowercase()),
+        };
+        by_sha256.or_else(|| match md5 {
+            "" => None,
+            h => self.md5_with_dir.get(&h.to_ascii_lowercase()),
+        })
+    }
+
     pub fn parents(&self) -> Result<HashSet<&Path>> {
         let parents: HashSet<&Path> = HashSet::from_iter(
             self.hashes()
-                .iter()
-                .flat_map(|(_, v)| v[0].parent())
+                .values()
+                .flat_map(|v| v[0].parent())
                 .collect::<Vec<&Path>>(),
         );
         filter_subdir(parents)
@@ -0,0 +1,79 @@
+use crate::bms::{BmsChart, Branches, Timing};
+use crate::bmson::Bmson;
+use crate::chart_hashes::is_bmson;
+use crate::errors::Result;
+use std::path::Path;
+
+/// metadata shared by bms and bmson charts
+#[derive(Debug, Clone, Default)]
+pub struct ChartInfo {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub artist: Option<String>,
+    pub subartist: Option<String>,
+    pub genre: Option<String>,
+    pub level: Option<String>,
+    pub notes: usize,
+    pub timing: Option<Timing>,
+}
+
+fn non_empty(s: String) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+impl From<BmsChart> for ChartInfo {
+    fn from(chart: BmsChart) -> Self {
+        let notes = chart.total_notes();
+        let timing = chart.timing();
+        let h = chart.header;
+        ChartInfo {
+            title: h.title,
+            subtitle: h.subtitle,
+            artist: h.artist,
+            subartist: h.subartist,
+            genre: h.genre,
+            level: h.playlevel,
+            notes,
+            timing,
+        }
+    }
+}
+
+impl From<Bmson> for ChartInfo {
+    fn from(bmson: Bmson) -> Self {
+        let notes = bmson.total_notes();
+        let timing = bmson.timing();
+        let info = bmson.info;
+        ChartInfo {
+            title: non_empty(info.title),
+            subtitle: non_empty(info.subtitle),
+            artist: non_empty(info.artist),
+            subartist: non_empty(info.subartists.join(" ")),
+            genre: non_empty(info.genre),
+            level: Some(info.level.to_string()),
+            notes,
+            timing,
+        }
+    }
+}
+
+impl ChartInfo {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        if is_bmson(path) {
+            return Ok(Bmson::from_path(path)?.into());
+        }
+
+        let mut info: ChartInfo = BmsChart::from_path(path)?.into();
+        if info.title.is_none() || info.artist.is_none() {
+            // headers may be defined only in the other #RANDOM branches
+            let all: ChartInfo = BmsChart::from_path_with(path, &Branches::All)?.into();
+            info.title = info.title.or(all.title);
+            info.artist = info.artist.or(all.artist);
+        }
+        Ok(info)
+    }
+}
@@ -0,0 +1,172 @@
+use crate::errors::Result;
+use encoding_rs::{Encoding, EUC_KR, SHIFT_JIS, UTF_8};
+use log::{debug, warn};
+use std::fs;
+use std::path::Path;
+
+/// put this file in a folder to force the encoding of its charts. e.g.) "euc-kr"
+pub const OVERRIDE_FILE_NAME: &str = ".beatman-encoding";
+
+const LOW_CONFIDENCE: f32 = 0.5;
+
+// in order of preference when the scores are the same
+const CANDIDATES: &[&Encoding] = &[UTF_8, SHIFT_JIS, EUC_KR];
+
+#[derive(Debug)]
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    /// 0.0 - 1.0
+    pub confidence: f32,
+}
+
+// how likely the char appears in a chart written by a human
+fn char_weight(c: char) -> f32 {
+    match c as u32 {
+        0x3040..=0x30FF => 1.0,          // hiragana, katakana
+        0xAC00..=0xD7A3 => 1.0,          // hangul
+        0x4E00..=0x9FFF => 0.9,          // kanji, hanja
+        0x3000..=0x303F => 1.0,          // CJK symbols
+        0xFF01..=0xFF60 => 1.0,          // fullwidth forms
+        0xFF61..=0xFF9F => 0.3,          // halfwidth katakana
+        0x00C0..=0x00FF => 0.8,          // latin-1 letters
+        0x00A0..=0x00BF => 0.5,          // latin-1 symbols
+        0x0370..=0x04FF => 0.5,          // greek, cyrillic
+        0x2000..=0x2BFF => 0.8,          // punctuation, arrows, ★, ♪, ...
+        0x3130..=0x318F => 0.5,          // hangul jamo
+        0xE000..=0xF8FF => 0.0,          // private use
+        0x0080..=0x009F | 0xFFFD => 0.0, // C1 controls, replacement
+        _ => 0.2,
+    }
+}
+
+fn score(text: &str) -> f32 {
+    let (sum, count) = text
+        .chars()
+        .filter(|c| !c.is_ascii())
+        .fold((0.0, 0), |(sum, count), c| {
+            (sum + char_weight(c), count + 1)
+        });
+    if count == 0 {
+        1.0
+    } else {
+        sum / count as f32
+    }
+}
+
+// encoding_rs decodes EUC-KR as UHC, whose extended area accepts most SJIS bytes.
+// real EUC-KR text rarely uses it, so the ratio of KS X 1001 characters is a good hint.
+fn ks_x_1001_ratio(bytes: &[u8]) -> f32 {
+    let (mut total, mut standard) = (0, 0);
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] < 0x80 {
+            i += 1;
+            continue;
+        }
+        total += 1;
+        if bytes[i] >= 0xA1 && bytes.get(i + 1).is_some_and(|b| *b >= 0xA1) {
+            standard += 1;
+        }
+        i += 2;
+    }
+    if total == 0 {
+        1.0
+    } else {
+        standard as f32 / total as f32
+    }
+}
+
+/// detect the encoding from BOM or the plausibility of decoded text
+pub fn detect(bytes: &[u8]) -> Decoded {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+        return Decoded {
+            text: text.into_owned(),
+            encoding,
+            confidence: 1.0,
+        };
+    }
+
+    let best = CANDIDATES
+        .iter()
+        .flat_map(|enc| {
+            enc.decode_without_bom_handling_and_without_replacement(bytes)
+                .map(|text| {
+                    let mut s = score(&text);
+                    if *enc == EUC_KR {
+                        s *= ks_x_1001_ratio(bytes);
+                    }
+                    (*enc, s, text)
+                })
+        })
+        .fold(
+            None,
+            |best: Option<(&'static Encoding, f32, _)>, cur| match best {
+                Some(b) if b.1 >= cur.1 => Some(b),
+                _ => Some(cur),
+            },
+        );
+
+    match best {
+        Some((encoding, confidence, text)) => Decoded {
+            text: text.into_owned(),
+            encoding,
+            confidence,
+        },
+        None => {
+            // broken in every candidate. SJIS is the most common in BMS
+            let (text, _) = SHIFT_JIS.decode_without_bom_handling(bytes);
+            Decoded {
+                text: text.into_owned(),
+                encoding: SHIFT_JIS,
+                confidence: 0.0,
+            }
+        }
+    }
+}
+
+pub fn decode(bytes: &[u8], forced: Option<&'static Encoding>) -> Decoded {
+    match forced {
+        Some(encoding) => {
+            let (text, _, _) = encoding.decode(bytes);
+            Decoded {
+                text: text.into_owned(),
+                encoding,
+                confidence: 1.0,
+            }
+        }
+        None => detect(bytes),
+    }
+}
+
+/// encoding specified by OVERRIDE_FILE_NAME in the folder
+pub fn folder_encoding(dir: &Path) -> Option<&'static Encoding> {
+    let label = fs::read_to_string(dir.join(OVERRIDE_FILE_NAME)).ok()?;
+    let encoding = Encoding::for_label(label.trim().as_bytes());
+    if encoding.is_none() {
+        warn!("unknown encoding {:?} in {:?}", label.trim(), dir);
+    }
+    encoding
+}
+
+pub fn read_file(path: &Path) -> Result<Decoded> {
+    let bytes = fs::read(path)?;
+    let forced = path.parent().and_then(folder_encoding);
+    let decoded = decode(&bytes, forced);
+    debug!(
+        "{:?} is decoded as {} (confidence {:.2})",
+        path,
+        decoded.encoding.name(),
+        decoded.confidence
+    );
+    if decoded.confidence < LOW_CONFIDENCE {
+        warn!(
+            "{:?} may be garbled as {}. put {} into the folder to specify the encoding",
+            path,
+            decoded.encoding.name(),
+            OVERRIDE_FILE_NAME
+        );
+    }
+    Ok(decoded)
+}
@@ -1,8 +1,48 @@
 use crate::errors::Result;
 use log::debug;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
+/// beatman's own files (caches, etc.) are stored here
+pub const APP_DIR_NAME: &str = ".beatman";
+
+pub fn app_dir(mydir: &Path) -> PathBuf {
+    mydir.join(APP_DIR_NAME)
+}
+
+/// path relative to mydir, used as a key in beatman's databases
+pub fn relative_key(mydir: &Path, path: &Path) -> String {
+    path.strip_prefix(mydir)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// rename, or copy and remove if the file is on another device
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    debug!("mv {:?} -> {:?}", from, to);
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// copy the dir recursively
+pub fn copy_dir(from: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let to = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to)?;
+        } else {
+            fs::copy(entry.path(), to)?;
+        }
+    }
+    Ok(())
+}
+
 pub fn move_and_remove_dir(from: &Path, dest: &Path) -> Result<()> {
     debug!("from: {:?}, dest: {:?}", from, dest);
 
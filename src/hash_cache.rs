@@ -0,0 +1,132 @@
+use crate::errors::Result;
+use crate::fsutil;
+use log::{debug, info};
+use rand::seq::SliceRandom;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const CACHE_FILE_NAME: &str = "hash_cache.db";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedHash {
+    pub size: u64,
+    /// nanoseconds since UNIX epoch
+    pub mtime: i64,
+    pub sha256: String,
+    pub md5: String,
+}
+
+/// size and mtime of the file, which decide whether a cached hash is still valid
+pub fn file_stat(path: &Path) -> Result<(u64, i64)> {
+    let meta = fs::metadata(path)?;
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH)?.as_nanos() as i64;
+    Ok((meta.len(), mtime))
+}
+
+/// hashes of charts stored in mydir, keyed by path + size + mtime
+pub struct HashCache {
+    root: PathBuf,
+    rebuild: bool,
+    verify_samples: usize,
+}
+
+impl HashCache {
+    pub fn new(root: &Path, rebuild: bool, verify_samples: usize) -> Self {
+        HashCache {
+            root: root.to_path_buf(),
+            rebuild,
+            verify_samples,
+        }
+    }
+
+    fn open(&self) -> Result<Connection> {
+        let dir = fsutil::app_dir(&self.root);
+        fs::create_dir_all(&dir)?;
+        let conn = Connection::open(dir.join(CACHE_FILE_NAME))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS hashes (
+                path TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                mtime INTEGER NOT NULL,
+                sha256 TEXT NOT NULL,
+                md5 TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(conn)
+    }
+
+    /// cached hashes of charts under the dir. empty if rebuilding.
+    pub fn load(&self, dir: &Path) -> Result<HashMap<PathBuf, CachedHash>> {
+        if self.rebuild {
+            info!("ignore hash cache and rehash all charts");
+            return Ok(HashMap::new());
+        }
+
+        let conn = self.open()?;
+        let mut stmt = conn.prepare("SELECT path, size, mtime, sha256, md5 FROM hashes")?;
+        let cached: HashMap<PathBuf, CachedHash> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<usize, String>(0)?,
+                    CachedHash {
+                        size: row.get(1)?,
+                        mtime: row.get(2)?,
+                        sha256: row.get(3)?,
+                        md5: row.get(4)?,
+                    },
+                ))
+            })?
+            .flatten()
+            .map(|(key, c)| (self.root.join(key), c))
+            .filter(|(path, _)| path.starts_with(dir))
+            .collect();
+        debug!("{} hashes are cached", cached.len());
+        Ok(cached)
+    }
+
+    pub fn store(&self, entries: &[(PathBuf, CachedHash)], removed: &[PathBuf]) -> Result<()> {
+        if entries.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.open()?;
+        let tx = conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO hashes (path, size, mtime, sha256, md5) VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (path, c) in entries {
+                insert.execute(params![
+                    fsutil::relative_key(&self.root, path),
+                    c.size,
+                    c.mtime,
+                    c.sha256,
+                    c.md5
+                ])?;
+            }
+            let mut delete = tx.prepare("DELETE FROM hashes WHERE path = ?1")?;
+            for path in removed {
+                delete.execute(params![fsutil::relative_key(&self.root, path)])?;
+            }
+        }
+        tx.commit()?;
+        debug!(
+            "hash cache: {} stored, {} removed",
+            entries.len(),
+            removed.len()
+        );
+        Ok(())
+    }
+
+    /// pick cached charts randomly to rehash
+    pub fn samples<'a>(&self, cached: &[&'a Path]) -> Vec<&'a Path> {
+        cached
+            .choose_multiple(&mut rand::thread_rng(), self.verify_samples)
+            .copied()
+            .collect()
+    }
+}
@@ -0,0 +1,203 @@
+use crate::chart_hashes::ChartHashes;
+use crate::errors::Result;
+use crate::fsutil;
+use crate::hash_cache::CachedHash;
+use log::debug;
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE_NAME: &str = "library.db";
+
+const COLUMNS: &str = "path, folder, sha256, md5, title, subtitle, artist, subartist, genre, level, notes, min_bpm, max_bpm, length, size, folder_size, mtime";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexedChart {
+    pub path: PathBuf,
+    pub folder: PathBuf,
+    pub sha256: String,
+    pub md5: String,
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub artist: Option<String>,
+    pub subartist: Option<String>,
+    pub genre: Option<String>,
+    pub level: Option<String>,
+    pub notes: u32,
+    pub min_bpm: Option<f64>,
+    pub max_bpm: Option<f64>,
+    /// seconds
+    pub length: Option<f64>,
+    pub size: u64,
+    /// total size of the files in the folder
+    pub folder_size: u64,
+    /// nanoseconds since UNIX epoch
+    pub mtime: i64,
+}
+
+/// index of every chart in mydir, built by the scan command
+pub struct Library {
+    root: PathBuf,
+    conn: Connection,
+}
+
+impl Library {
+    pub fn open(mydir: &Path) -> Result<Self> {
+        let dir = fsutil::app_dir(mydir);
+        fs::create_dir_all(&dir)?;
+        let conn = Connection::open(dir.join(INDEX_FILE_NAME))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS charts (
+                path TEXT PRIMARY KEY,
+                folder TEXT NOT NULL,
+                sha256 TEXT NOT NULL,
+                md5 TEXT NOT NULL,
+                title TEXT,
+                subtitle TEXT,
+                artist TEXT,
+                subartist TEXT,
+                genre TEXT,
+                level TEXT,
+                notes INTEGER NOT NULL,
+                min_bpm REAL,
+                max_bpm REAL,
+                length REAL,
+                size INTEGER NOT NULL,
+                folder_size INTEGER NOT NULL,
+                mtime INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS charts_sha256 ON charts(sha256);
+            CREATE INDEX IF NOT EXISTS charts_md5 ON charts(md5);
+            CREATE INDEX IF NOT EXISTS charts_folder ON charts(folder);",
+        )?;
+        Ok(Library {
+            root: mydir.to_path_buf(),
+            conn,
+        })
+    }
+
+    fn read_row(&self, row: &Row) -> rusqlite::Result<IndexedChart> {
+        Ok(IndexedChart {
+            path: self.root.join(row.get::<usize, String>(0)?),
+            folder: self.root.join(row.get::<usize, String>(1)?),
+            sha256: row.get(2)?,
+            md5: row.get(3)?,
+            title: row.get(4)?,
+            subtitle: row.get(5)?,
+            artist: row.get(6)?,
+            subartist: row.get(7)?,
+            genre: row.get(8)?,
+            level: row.get(9)?,
+            notes: row.get(10)?,
+            min_bpm: row.get(11)?,
+            max_bpm: row.get(12)?,
+            length: row.get(13)?,
+            size: row.get(14)?,
+            folder_size: row.get(15)?,
+            mtime: row.get(16)?,
+        })
+    }
+
+    fn query(&self, condition: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<IndexedChart>> {
+        let sql = format!("SELECT {} FROM charts {} ORDER BY path", COLUMNS, condition);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let charts = stmt
+            .query_map(params, |row| self.read_row(row))?
+            .collect::<rusqlite::Result<Vec<IndexedChart>>>()?;
+        Ok(charts)
+    }
+
+    pub fn charts(&self) -> Result<Vec<IndexedChart>> {
+        self.query("", &[])
+    }
+
+    pub fn find_by_hash(&self, sha256: &str, md5: &str) -> Result<Vec<IndexedChart>> {
+        self.query(
+            "WHERE sha256 = ?1 OR md5 = ?2",
+            &[&sha256.to_ascii_lowercase(), &md5.to_ascii_lowercase()],
+        )
+    }
+
+    /// size and mtime of indexed charts
+    pub fn stats(&self) -> Result<HashMap<PathBuf, (u64, i64)>> {
+        let mut stmt = self.conn.prepare("SELECT path, size, mtime FROM charts")?;
+        let stats = stmt
+            .query_map([], |row| {
+                Ok((
+                    self.root.join(row.get::<usize, String>(0)?),
+                    (row.get(1)?, row.get(2)?),
+                ))
+            })?
+            .collect::<rusqlite::Result<HashMap<PathBuf, (u64, i64)>>>()?;
+        Ok(stats)
+    }
+
+    pub fn upsert(&mut self, charts: &[IndexedChart]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(&format!(
+                "INSERT OR REPLACE INTO charts ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+                COLUMNS
+            ))?;
+            for c in charts {
+                debug!("index {:?}", c.path);
+                stmt.execute(params![
+                    fsutil::relative_key(&self.root, &c.path),
+                    fsutil::relative_key(&self.root, &c.folder),
+                    c.sha256,
+                    c.md5,
+                    c.title,
+                    c.subtitle,
+                    c.artist,
+                    c.subartist,
+                    c.genre,
+                    c.level,
+                    c.notes,
+                    c.min_bpm,
+                    c.max_bpm,
+                    c.length,
+                    c.size,
+                    c.folder_size,
+                    c.mtime,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare("DELETE FROM charts WHERE path = ?1")?;
+            for p in paths {
+                debug!("unindex {:?}", p);
+                stmt.execute(params![fsutil::relative_key(&self.root, p)])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// hashes of indexed charts, without walking the filesystem
+    pub fn chart_hashes(&self) -> Result<ChartHashes> {
+        let entries = self
+            .charts()?
+            .into_iter()
+            .map(|c| {
+                (
+                    c.path,
+                    CachedHash {
+                        size: c.size,
+                        mtime: c.mtime,
+                        sha256: c.sha256,
+                        md5: c.md5,
+                    },
+                )
+            })
+            .collect();
+        Ok(ChartHashes::from_entries(entries))
+    }
+}
@@ -3,10 +3,27 @@ use serde::Serialize;
 use std::path::{Path, PathBuf};
 mod errors;
 use errors::Result;
+use hash_cache::HashCache;
+use manifest::Manifest;
+use ops::install_from_dir::InstallOptions;
+use table_cache::TableCache;
+use table_registry::TableRegistry;
+mod archive;
+mod base_folder;
+mod bms;
+mod bmson;
 mod chart_hashes;
+mod chart_info;
+mod encoding;
 mod fsutil;
+mod hash_cache;
+mod library;
+mod manifest;
 mod ops;
+mod sounds;
+mod table_cache;
 mod table_loader;
+mod table_registry;
 mod utils;
 use clap::{Parser, Subcommand};
 use std::env;
@@ -24,6 +41,19 @@ struct Cli {
     #[clap(short, long, help = "your bms directory")]
     mydir: PathBuf,
 
+    #[clap(long, help = "ignore the hash cache and rehash all charts")]
+    rehash: bool,
+
+    #[clap(
+        long,
+        default_value = "0",
+        help = "rehash this number of cached charts randomly to verify the hash cache"
+    )]
+    verify_cache: usize,
+
+    #[clap(long, help = "use cached difficulty tables without fetching")]
+    offline: bool,
+
     #[clap(subcommand)]
     command: Commands,
 }
@@ -35,15 +65,27 @@ enum Commands {
         #[clap(
             short,
             long,
-            help = "table or score.json url. e.g.) https://stellabms.xyz/sl/table.html"
+            help = "table url, path of header.json / score.json or symbol of a registered table. e.g.) https://stellabms.xyz/sl/table.html"
         )]
         table_url: String,
 
         #[clap(long, help = "check level limit")]
-        level_limit: Option<u8>,
+        level_limit: Option<String>,
 
         #[clap(long, help = "check level lower limit")]
-        level_lower_limit: Option<u8>,
+        level_lower_limit: Option<String>,
+
+        #[clap(
+            long,
+            help = "use the library index built by scan instead of hashing charts"
+        )]
+        use_index: bool,
+
+        #[clap(
+            long,
+            help = "write missing charts to this json file, and their urls to the file with the extension replaced by .urls.txt (e.g. missing.json -> missing.urls.txt)"
+        )]
+        export: Option<PathBuf>,
     },
 
     #[clap(about = "install from zip files into mydir")]
@@ -53,6 +95,28 @@ enum Commands {
 
         #[clap(short, long, help = "if install recursively from nested directory")]
         recursive: bool,
+
+        #[clap(long, help = "manifest exported by check, to verify installed charts")]
+        expect: Option<PathBuf>,
+
+        #[clap(
+            long,
+            default_value = "2",
+            help = "extract archives in archives up to this depth"
+        )]
+        nest_depth: usize,
+
+        #[clap(long, help = "leave source archives and folders as they are")]
+        keep_source: bool,
+
+        #[clap(
+            long,
+            help = "password of encrypted archives. can be specified multiple times"
+        )]
+        password: Vec<String>,
+
+        #[clap(long, help = "file listing passwords, one per line")]
+        password_file: Option<PathBuf>,
     },
 
     #[clap(about = "organize (merge & reconstruct) your directory")]
@@ -68,6 +132,54 @@ enum Commands {
         shard: bool,
     },
 
+    #[clap(about = "report how many charts you have per level of registered tables")]
+    Report {
+        #[clap(help = "symbols or urls of tables. all registered tables if omitted")]
+        tables: Vec<String>,
+
+        #[clap(long, arg_enum, default_value = "terminal")]
+        format: ops::report::ReportFormat,
+
+        #[clap(short, long, help = "write the report to this file instead of stdout")]
+        output: Option<PathBuf>,
+
+        #[clap(long, help = "use the library index built by scan")]
+        use_index: bool,
+    },
+
+    #[clap(about = "build or update the library index of your charts")]
+    Scan {
+        #[clap(long, help = "re-index all charts even if they are not modified")]
+        full: bool,
+    },
+
+    #[clap(about = "search charts in the library index")]
+    Search {
+        #[clap(long, help = "title (fuzzy)")]
+        title: Option<String>,
+
+        #[clap(long, help = "artist (fuzzy)")]
+        artist: Option<String>,
+
+        #[clap(long, help = "genre (partial match)")]
+        genre: Option<String>,
+
+        #[clap(long, help = "sha256 or md5 (exact match)")]
+        hash: Option<String>,
+
+        #[clap(long, help = "#PLAYLEVEL lower limit")]
+        level_min: Option<f64>,
+
+        #[clap(long, help = "#PLAYLEVEL upper limit")]
+        level_max: Option<f64>,
+    },
+
+    #[clap(about = "manage registered difficulty tables")]
+    Table {
+        #[clap(subcommand)]
+        command: TableCommands,
+    },
+
     #[clap(about = "rename your directories")]
     Rename {},
 
@@ -82,7 +194,7 @@ enum Commands {
         #[clap(long)]
         folder_default_json: PathBuf,
         #[clap(long)]
-        lower_limit_level: u8,
+        lower_limit_level: String,
         #[clap(long, help = "AEASY / EASY / NORMAL / HARD / EXHARD")]
         target_lamp: String,
         #[clap(long)]
@@ -102,10 +214,39 @@ enum Commands {
     },
 }
 
+#[derive(Subcommand)]
+enum TableCommands {
+    #[clap(about = "register a table to refer to it by its symbol")]
+    Add {
+        #[clap(help = "table url or path of header.json / score.json")]
+        url: String,
+
+        #[clap(long, help = "use this instead of the symbol in the header")]
+        symbol: Option<String>,
+    },
+
+    #[clap(about = "fetch registered tables and show changes")]
+    Update {
+        #[clap(help = "symbols of tables to update. all tables if omitted")]
+        symbols: Vec<String>,
+    },
+
+    #[clap(about = "list registered tables")]
+    List {},
+
+    #[clap(about = "unregister a table")]
+    Remove { symbol: String },
+}
+
 #[derive(strum_macros::Display, Serialize, Debug)]
 pub enum FrontendMsg {
     CheckNotFound,
     CheckSummary,
+    SearchResult,
+    TableList,
+    TableUpdate,
+    CoverageReport,
+    InstallVerification,
 }
 
 #[derive(Serialize, Debug)]
@@ -155,30 +296,71 @@ fn main() -> Result<()> {
     }
 
     let dryrun = cli.dryrun;
+    let hash_cache = HashCache::new(mydir, cli.rehash, cli.verify_cache);
+    let table_cache = TableCache::new(mydir, cli.offline);
 
     match &cli.command {
         Commands::Check {
             table_url,
             level_limit,
             level_lower_limit,
+            use_index,
+            export,
         } => {
             ops::check_table_coverage::check_table_coverage(
-                table_url,
+                &TableRegistry::open(mydir)?.resolve(table_url),
                 mydir,
+                &hash_cache,
+                &table_cache,
+                *use_index,
                 level_limit,
                 level_lower_limit,
+                export,
             )?;
         }
-        Commands::Install { from, recursive } => {
+        Commands::Install {
+            from,
+            recursive,
+            expect,
+            nest_depth,
+            keep_source,
+            password,
+            password_file,
+        } => {
             let from = &Path::new(&from);
             if !from.is_dir() {
                 error!("from is not a directory");
                 return Err("from is not a directory".into());
             }
-            if *recursive {
-                ops::install_from_dir::install_from_dirs(from, mydir, dryrun)?;
+            let manifest = expect.as_deref().map(Manifest::read).transpose()?;
+            let mut passwords = password.clone();
+            if let Some(path) = password_file {
+                passwords.extend(ops::install_from_dir::read_password_file(path)?);
+            }
+            let options = InstallOptions {
+                nest_depth: *nest_depth,
+                keep_source: *keep_source,
+                passwords,
+            };
+            let installation = if *recursive {
+                ops::install_from_dir::install_from_dirs(
+                    from,
+                    mydir,
+                    &hash_cache,
+                    &options,
+                    dryrun,
+                )?
             } else {
-                ops::install_from_dir::install_from_dir(from, mydir, dryrun)?;
+                ops::install_from_dir::install_from_dir(from, mydir, &hash_cache, &options, dryrun)?
+            };
+            if let Some(manifest) = manifest {
+                ops::install_from_dir::verify_installed(&manifest, installation.installed)?;
+            }
+            ops::install_from_dir::report_failures(&installation.failed);
+            if !installation.failed.is_empty() {
+                return Err(
+                    format!("{} archives failed to install", installation.failed.len()).into(),
+                );
             }
         }
         Commands::Organize { dest, shard } => {
@@ -189,14 +371,65 @@ fn main() -> Result<()> {
             }
 
             info!("== rename ==");
-            ops::rename::rename_dirs(mydir, dryrun)?;
+            ops::rename::rename_dirs(mydir, &hash_cache, dryrun)?;
             info!("== merge ==");
-            ops::merge::merge(mydir, dryrun)?;
+            ops::merge::merge(mydir, &hash_cache, dryrun)?;
             info!("== reconstruct ==");
-            ops::reconstruct::reconstruct(mydir, dest, dryrun, *shard)?;
+            ops::reconstruct::reconstruct(mydir, dest, &hash_cache, dryrun, *shard)?;
+        }
+        Commands::Report {
+            tables,
+            format,
+            output,
+            use_index,
+        } => {
+            ops::report::coverage_report(
+                mydir,
+                &hash_cache,
+                &table_cache,
+                tables,
+                *use_index,
+                *format,
+                output,
+            )?;
         }
+        Commands::Scan { full } => {
+            ops::scan::scan(mydir, &hash_cache, *full)?;
+        }
+        Commands::Search {
+            title,
+            artist,
+            genre,
+            hash,
+            level_min,
+            level_max,
+        } => {
+            let query = ops::search::SearchQuery {
+                title: title.clone(),
+                artist: artist.clone(),
+                genre: genre.clone(),
+                hash: hash.clone(),
+                level_min: *level_min,
+                level_max: *level_max,
+            };
+            ops::search::search(mydir, &query)?;
+        }
+        Commands::Table { command } => match command {
+            TableCommands::Add { url, symbol } => {
+                ops::table::add_table(mydir, &table_cache, url, symbol)?;
+            }
+            TableCommands::Update { symbols } => {
+                ops::table::update_tables(mydir, &table_cache, symbols)?;
+            }
+            TableCommands::List {} => {
+                ops::table::list_tables(mydir, &table_cache)?;
+            }
+            TableCommands::Remove { symbol } => {
+                ops::table::remove_table(mydir, symbol)?;
+            }
+        },
         Commands::Rename {} => {
-            ops::rename::rename_dirs(mydir, dryrun)?;
+            ops::rename::rename_dirs(mydir, &hash_cache, dryrun)?;
         }
         Commands::Task {
             table_url,
@@ -208,11 +441,12 @@ fn main() -> Result<()> {
             task_notes,
         } => {
             ops::create_task_folder::create_task_folder(
-                table_url,
+                &TableRegistry::open(mydir)?.resolve(table_url),
+                &table_cache,
                 player_score_path,
                 songdata_path,
                 folder_default_json,
-                *lower_limit_level,
+                lower_limit_level,
                 target_lamp,
                 *task_notes,
             )?;
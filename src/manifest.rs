@@ -0,0 +1,127 @@
+use crate::chart_hashes::ChartHashes;
+use crate::errors::Result;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestChart {
+    pub title: String,
+    pub level: String,
+    #[serde(default)]
+    pub sha256: String,
+    #[serde(default)]
+    pub md5: String,
+    pub url_diff: Option<String>,
+}
+
+/// charts downloaded from the same url
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestPackage {
+    pub url: String,
+    /// the base song is owned, only diffs are needed
+    pub base_folder: Option<PathBuf>,
+    pub charts: Vec<ManifestChart>,
+}
+
+/// result of comparing installed charts with a manifest
+#[derive(Serialize, Debug, Default)]
+pub struct Verification {
+    pub satisfied: Vec<ManifestChart>,
+    pub missing: Vec<ManifestChart>,
+    /// installed charts not in the manifest
+    pub unexpected: Vec<PathBuf>,
+}
+
+/// missing charts exported by check
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    pub table: String,
+    pub generated_at: String,
+    pub packages: Vec<ManifestPackage>,
+}
+
+impl Manifest {
+    pub fn new(table: &str) -> Self {
+        Manifest {
+            table: table.to_owned(),
+            generated_at: chrono::Local::now().to_rfc3339(),
+            packages: vec![],
+        }
+    }
+
+    /// group by url, in order of appearance
+    pub fn push(&mut self, url: &str, base_folder: Option<PathBuf>, chart: ManifestChart) {
+        match self.packages.iter_mut().find(|p| p.url == url) {
+            Some(p) => {
+                p.base_folder = p.base_folder.take().or(base_folder);
+                p.charts.push(chart);
+            }
+            None => self.packages.push(ManifestPackage {
+                url: url.to_owned(),
+                base_folder,
+                charts: vec![chart],
+            }),
+        }
+    }
+
+    /// urls to download. packages whose base songs are owned need the diffs only
+    pub fn urls(&self) -> Vec<String> {
+        let mut urls: Vec<String> = vec![];
+        for p in &self.packages {
+            let diffs = p.charts.iter().flat_map(|c| c.url_diff.clone());
+            let package = (p.base_folder.is_none() && !p.url.is_empty()).then(|| p.url.clone());
+            for url in package.into_iter().chain(diffs) {
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+            }
+        }
+        urls
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn verify(&self, installed: &ChartHashes) -> Verification {
+        let mut v = Verification::default();
+        let charts = self.packages.iter().flat_map(|p| p.charts.iter());
+        for c in charts.clone() {
+            if installed.find(&c.sha256, &c.md5).is_some() {
+                v.satisfied.push(c.clone());
+            } else {
+                v.missing.push(c.clone());
+            }
+        }
+        let is_expected = |sha256: &str, md5: &str| {
+            charts
+                .clone()
+                .any(|c| c.sha256.eq_ignore_ascii_case(sha256) || c.md5.eq_ignore_ascii_case(md5))
+        };
+        v.unexpected = installed
+            .entries()
+            .iter()
+            .filter(|(_, h)| !is_expected(&h.sha256, &h.md5))
+            .map(|(path, _)| path.clone())
+            .collect();
+        v
+    }
+
+    /// write the manifest as json, and the urls as a text file next to it.
+    /// the extension is replaced, e.g.) missing.json -> missing.urls.txt
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        let urls_path = path.with_extension("urls.txt");
+        let urls: String = self.urls().iter().map(|u| format!("{}\n", u)).collect();
+        fs::write(&urls_path, urls)?;
+        info!(
+            "exported {} packages to {:?} and {:?}",
+            self.packages.len(),
+            path,
+            urls_path
+        );
+        Ok(())
+    }
+}
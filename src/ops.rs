@@ -5,3 +5,7 @@ pub mod install_from_dir;
 pub mod merge;
 pub mod reconstruct;
 pub mod rename;
+pub mod report;
+pub mod scan;
+pub mod search;
+pub mod table;
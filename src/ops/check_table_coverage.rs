@@ -1,9 +1,14 @@
+use crate::base_folder::BaseFolders;
 use crate::chart_hashes::ChartHashes;
 use crate::errors::Result;
+use crate::hash_cache::HashCache;
+use crate::library::Library;
+use crate::manifest::{Manifest, ManifestChart};
+use crate::table_cache::TableCache;
 use crate::{table_loader, FrontendMsg};
 use log::{debug, info};
 use serde::Serialize;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Serialize, Debug)]
 struct NotFoundInfo {
@@ -11,66 +16,125 @@ struct NotFoundInfo {
     pub title: String,
     pub url: String,
     pub diff_url: Option<String>,
+    /// the base song is owned and only the diff is missing
+    pub base_folder: Option<PathBuf>,
 }
 
 #[derive(Serialize, Debug)]
 struct CheckSummary {
     pub found: i32,
     pub total: i32,
+    /// missing charts whose base songs are owned
+    pub diff_only: i32,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn check_table_coverage(
     score_url: &str,
     current_dir: &Path,
-    level_limit: &Option<u8>,
-    level_lower_limit: &Option<u8>,
+    hash_cache: &HashCache,
+    table_cache: &TableCache,
+    use_index: bool,
+    level_limit: &Option<String>,
+    level_lower_limit: &Option<String>,
+    export: &Option<PathBuf>,
 ) -> Result<()> {
-    let table = table_loader::TableLoader::new(score_url)?;
-    let chart_hashes = ChartHashes::new(current_dir)?;
+    let table = table_loader::TableLoader::new(score_url, table_cache)?;
+    let header = table.header();
+    if !header.name.is_empty() {
+        info!(
+            "table: {} ({}), {} courses",
+            header.name,
+            header.symbol,
+            header.course.len()
+        );
+    }
+    let level_limit = level_limit
+        .as_ref()
+        .map(|l| table.parse_level(l))
+        .transpose()?;
+    let level_lower_limit = level_lower_limit
+        .as_ref()
+        .map(|l| table.parse_level(l))
+        .transpose()?;
+    let library = Library::open(current_dir)?;
+    let chart_hashes = if use_index {
+        library.chart_hashes()?
+    } else {
+        ChartHashes::new(current_dir, hash_cache)?
+    };
+    let base_folders = BaseFolders::new(&library.charts()?);
+    if base_folders.is_empty() {
+        info!("run scan to find base songs of missing diffs");
+    }
 
-    let filtered_table = table
-        .charts()
-        .iter()
-        .filter(|sd| {
-            level_limit.map_or(true, |l| {
-                sd.level.parse().map(|t: u8| t <= l).unwrap_or(true)
-            })
-        })
-        .filter(|sd| {
-            level_lower_limit.map_or(true, |l| {
-                sd.level.parse().map(|t: u8| l <= t).unwrap_or(true)
-            })
-        });
+    let filtered_table = table.charts().iter().filter(|sd| {
+        let level = table.level(&sd.level);
+        level_limit.as_ref().is_none_or(|l| &level <= l)
+            && level_lower_limit.as_ref().is_none_or(|l| l <= &level)
+    });
 
     let mut total = 0;
     let mut counter = 0;
+    let mut diff_only = 0;
+    let mut manifest = Manifest::new(score_url);
     filtered_table.for_each(|sd| {
         total += 1;
-        let hashes = chart_hashes.hashes();
-        if hashes.contains_key(&sd.sha256) {
-            debug!("found! {:?}", hashes.get(&sd.sha256).unwrap()[0]);
+        if let Some(paths) = chart_hashes.find(&sd.sha256, &sd.md5) {
+            debug!("found! {:?}", paths[0]);
             counter += 1;
         } else {
             info!("not found! [{}] {}", sd.level, sd.title);
-            info!("--url--> {}", sd.url);
+            let base_folder = if sd.url_diff.is_empty() {
+                None
+            } else {
+                base_folders.find(&sd.title, &sd.artist)
+            };
+            match &base_folder {
+                Some(folder) => {
+                    diff_only += 1;
+                    info!("--base--> {:?} (download the diff only)", folder);
+                }
+                None => info!("--url--> {}", sd.url),
+            }
             if !sd.url_diff.is_empty() {
                 info!("--diff--> {}", sd.url_diff);
             }
+            manifest.push(
+                &sd.url,
+                base_folder.clone(),
+                ManifestChart {
+                    title: sd.title.clone(),
+                    level: sd.level.clone(),
+                    sha256: sd.sha256.clone(),
+                    md5: sd.md5.clone(),
+                    url_diff: (!sd.url_diff.is_empty()).then(|| sd.url_diff.clone()),
+                },
+            );
             info!(target: &FrontendMsg::CheckNotFound.to_string(), "{}", 
             serde_json::to_string(
                 &NotFoundInfo { 
                 level: sd.level.clone(), title: sd.title.clone(), url: sd.url.clone(), 
-                diff_url: if sd.url_diff.is_empty() { None } else {Some(sd.url_diff.clone())}
+                diff_url: if sd.url_diff.is_empty() { None } else {Some(sd.url_diff.clone())},
+                base_folder,
             }).unwrap());
         }
     });
 
+    if let Some(path) = export {
+        manifest.write(path)?;
+    }
+
     info!("{} / {} charts found", counter, total);
+    if diff_only > 0 {
+        info!("{} of missing charts need the diff only", diff_only);
+    }
     info!(target: &FrontendMsg::CheckSummary.to_string(), "{}", 
     serde_json::to_string(
         &CheckSummary { 
             found: counter, 
-            total  
+            total,
+            diff_only,
           }).unwrap());
     Ok(())
 }
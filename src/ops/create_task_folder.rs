@@ -1,5 +1,6 @@
 use crate::errors::Result;
-use crate::table_loader;
+use crate::table_cache::TableCache;
+use crate::table_loader::{self, TableLevel};
 use crate::utils::{add_and_write_table_json, lamp_to_id, DefaultTableSong};
 use chrono::Local;
 use log::{debug, info};
@@ -9,8 +10,9 @@ use std::path::Path;
 #[derive(Debug, Clone)]
 struct TableData {
     sha256: String,
+    md5: String,
     title: String,
-    level: u32,
+    level: TableLevel,
 }
 
 #[derive(Debug, Clone)]
@@ -27,7 +29,6 @@ struct Score {
 
 #[derive(Debug, Clone)]
 struct ChartInfo {
-    #[allow(dead_code)]
     sha256: String,
     totalnotes: u32,
 }
@@ -39,12 +40,14 @@ struct TableDataWithScore {
     totalnotes: u32,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_task_folder(
     table_url: &str,
+    table_cache: &TableCache,
     player_score_path: &Path,
     songdata_path: &Path,
     folder_default_json_path: &Path,
-    lower_limit_level: u8,
+    lower_limit_level: &str,
     target_lamp: &str,
     task_notes: u32,
 ) -> Result<()> {
@@ -56,38 +59,49 @@ pub fn create_task_folder(
     let songdata = Connection::open_with_flags(songdata_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
 
     info!("load table {:?}", table_url);
-    let table = table_loader::TableLoader::new(table_url)?;
+    let table = table_loader::TableLoader::new(table_url, table_cache)?;
+    let lower_limit_level = table.parse_level(lower_limit_level)?;
     let table: Vec<TableData> = table
         .charts()
         .iter()
-        .filter(|sd| {
-            sd.level
-                .parse()
-                .map(|t: u8| t >= lower_limit_level)
-                .unwrap_or(true)
-        })
-        .map(|sd| {
-            let u_level = sd.level.parse()?;
-            Ok(TableData {
-                sha256: sd.sha256.to_owned(),
-                title: sd.title.to_owned(),
-                level: u_level,
-            })
+        .map(|sd| TableData {
+            sha256: sd.sha256.to_owned(),
+            md5: sd.md5.to_owned(),
+            title: sd.title.to_owned(),
+            level: table.level(&sd.level),
         })
-        .collect::<Result<Vec<TableData>>>()?;
+        .filter(|td| td.level >= lower_limit_level)
+        .collect();
 
     info!("append clear data for {:?} charts", table.len());
     let mut query_player_score_stmt = player_scores.prepare(
         "SELECT sha256, clear, playcount, minbp, scorehash FROM score WHERE sha256 = :sha256 LIMIT 1",
     )?;
-    let mut chart_info_stmt =
-        songdata.prepare("SELECT sha256, notes FROM song WHERE sha256 = :sha256 LIMIT 1")?;
+    // some tables have md5 only. resolve sha256 from songdata
+    let mut chart_info_stmt = songdata
+        .prepare("SELECT sha256, notes FROM song WHERE sha256 = :sha256 OR md5 = :md5 LIMIT 1")?;
 
     let target_charts = table.iter().map(|td| {
-        debug!("target hash {:?}", td.sha256);
+        debug!("target hash {:?} (md5: {:?})", td.sha256, td.md5);
+
+        let non_empty = |h: &String| (!h.is_empty()).then(|| h.clone());
+        let chart_info = chart_info_stmt
+            .query_map(
+                named_params! { ":sha256": non_empty(&td.sha256), ":md5": non_empty(&td.md5) },
+                |row| {
+                    Ok(ChartInfo {
+                        sha256: row.get(0)?,
+                        // total is REAL type in SQLite. convert it as u32
+                        totalnotes: ((row.get::<usize, f32>(1)?).round() as u32),
+                    })
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        let chart_info = chart_info.flatten().next().ok_or("Chart Info Not Found")?;
+        debug!("chart_info {:?}", chart_info);
 
         let player_score = query_player_score_stmt
-            .query_map(named_params! { ":sha256": td.sha256 }, |row| {
+            .query_map(named_params! { ":sha256": chart_info.sha256 }, |row| {
                 Ok(Score {
                     sha256: row.get(0)?,
                     clear: row.get(1)?,
@@ -103,20 +117,13 @@ pub fn create_task_folder(
             debug!("player_score {:?}", player_score);
         }
 
-        let chart_info = chart_info_stmt
-            .query_map(named_params! { ":sha256": td.sha256 }, |row| {
-                Ok(ChartInfo {
-                    sha256: row.get(0)?,
-                    // total is REAL type in SQLite. convert it as u32
-                    totalnotes: ((row.get::<usize, f32>(1)?).round() as u32),
-                })
-            })
-            .map_err(|e| e.to_string())?;
-        let chart_info = chart_info.flatten().next().ok_or("Chart Info Not Found")?;
-        debug!("chart_info {:?}", chart_info);
+        let table_data = TableData {
+            sha256: chart_info.sha256,
+            ..(*td).clone()
+        };
 
         Ok::<TableDataWithScore, Box<dyn std::error::Error>>(TableDataWithScore {
-            table_data: (*td).clone(),
+            table_data,
             score: player_score,
             totalnotes: chart_info.totalnotes,
         })
@@ -135,14 +142,14 @@ pub fn create_task_folder(
         .collect();
 
     // sort by (level, clear_lamp, playcount)
-    let cmpfunc = |a: &TableDataWithScore| -> (u32, u8, u32) {
+    let cmpfunc = |a: &TableDataWithScore| -> (TableLevel, u8, u32) {
         (
-            a.table_data.level,
+            a.table_data.level.clone(),
             a.score.as_ref().map_or(0, |s| s.clear),
             a.score.as_ref().map_or(0, |s| s.playcount),
         )
     };
-    non_achieved_charts.sort_by(|a, b| cmpfunc(a).partial_cmp(&cmpfunc(b)).unwrap());
+    non_achieved_charts.sort_by_key(cmpfunc);
     let mut notes = 0;
     let tasks: Vec<DefaultTableSong> = non_achieved_charts
         .into_iter()
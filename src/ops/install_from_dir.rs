@@ -1,52 +1,606 @@
+use crate::archive::{self, ArchiveError, ArchiveFormat, ErrorKind};
+use crate::base_folder::{BaseFolders, MIN_SOUND_COVERAGE};
+use crate::chart_hashes::{filter_bms_files, ChartHashes};
+use crate::chart_info::ChartInfo;
+use crate::encoding;
 use crate::errors::Result;
+use crate::fsutil;
+use crate::hash_cache::{CachedHash, HashCache};
+use crate::library::Library;
+use crate::manifest::Manifest;
 use crate::ops::rename::rename_dirs;
+use crate::sounds::{referenced_sounds, SoundFiles};
+use crate::FrontendMsg;
 use chrono::{DateTime, Utc};
-use core::str;
+use encoding_rs::Encoding;
 use log::{debug, info, warn};
-use std::ffi::OsStr;
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
-use unrar::Archive;
+use std::path::{Path, PathBuf};
 
-pub fn install_from_dirs(target_dir: &Path, dest_dir: &Path, dryrun: bool) -> Result<()> {
+/// charts installed and their hashes
+pub type InstalledCharts = Vec<(PathBuf, CachedHash)>;
+
+/// result of install
+#[derive(Default)]
+pub struct Installation {
+    pub installed: InstalledCharts,
+    /// archives failed to install, and why
+    pub failed: Vec<(PathBuf, ArchiveError)>,
+}
+
+impl Installation {
+    fn extend(&mut self, other: Installation) {
+        self.installed.extend(other.installed);
+        self.failed.extend(other.failed);
+    }
+}
+
+pub fn report_failures(failed: &[(PathBuf, ArchiveError)]) {
+    if failed.is_empty() {
+        return;
+    }
+    warn!("{} archives failed to install:", failed.len());
+    for (path, e) in failed {
+        warn!("  {:?}: {}", path, e);
+    }
+}
+
+/// report which charts of the manifest are installed
+pub fn verify_installed(manifest: &Manifest, installed: InstalledCharts) -> Result<()> {
+    let v = manifest.verify(&ChartHashes::from_entries(installed));
+    for c in &v.satisfied {
+        info!("installed: [{}] {}", c.level, c.title);
+    }
+    for c in &v.missing {
+        warn!("still missing: [{}] {}", c.level, c.title);
+    }
+    for p in &v.unexpected {
+        warn!("unexpected: {:?}", p);
+    }
+    info!(
+        "{} installed, {} still missing, {} unexpected",
+        v.satisfied.len(),
+        v.missing.len(),
+        v.unexpected.len()
+    );
+    info!(target: &FrontendMsg::InstallVerification.to_string(), "{}", serde_json::to_string(&v)?);
+    Ok(())
+}
+
+/// packages are extracted here before being moved into song folders
+const STAGING_DIR_NAME: &str = "staging";
+
+/// source archives are moved here after install, instead of being deleted
+const PROCESSED_DIR_NAME: &str = "processed";
+/// source archives failed to install are moved here
+const FAILED_DIR_NAME: &str = "failed";
+
+pub struct InstallOptions {
+    /// how deep archives in archives are extracted
+    pub nest_depth: usize,
+    /// leave source archives and folders as they are
+    pub keep_source: bool,
+    /// tried in order for encrypted archives
+    pub passwords: Vec<String>,
+}
+
+/// passwords listed one per line
+pub fn read_password_file(path: &Path) -> Result<Vec<String>> {
+    Ok(encoding::read_file(path)?
+        .text
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_owned())
+        .collect())
+}
+
+fn find_archives(dir: &Path, archives: &mut Vec<(PathBuf, ArchiveFormat)>) {
+    for path in entries(dir) {
+        if path.is_dir() {
+            find_archives(&path, archives);
+        } else if let Some(format) = ArchiveFormat::detect(&path) {
+            archives.push((path, format));
+        }
+    }
+}
+
+/// extract archives in the package, e.g.) ogg version or diffs bundled with the song.
+/// their contents are merged into the folders they are in
+fn extract_nested(
+    dir: &Path,
+    depth: usize,
+    name_encoding: Option<&'static Encoding>,
+    passwords: &[String],
+) -> Result<()> {
+    if depth == 0 {
+        return Ok(());
+    }
+    let mut archives = vec![];
+    find_archives(dir, &mut archives);
+    for (path, format) in archives {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let extracted = path.with_file_name(format!("{}.extracted", name));
+        info!("extracting nested archive {:?}", path);
+        if let Err(e) = archive::extract(&path, &extracted, format, name_encoding, passwords) {
+            warn!("failed to extract {:?}: {}", path, e);
+            if extracted.exists() {
+                fs::remove_dir_all(&extracted)?;
+            }
+            continue;
+        }
+        extract_nested(&extracted, depth - 1, name_encoding, passwords)?;
+        fs::remove_file(&path)?;
+        if let Some(parent) = path.parent() {
+            fsutil::move_and_remove_dir(&strip_root(&extracted), parent)?;
+        }
+        if extracted.exists() {
+            fs::remove_dir_all(&extracted)?;
+        }
+    }
+    Ok(())
+}
+
+// files added by OSes, not by the package author
+fn is_junk(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default();
+    ["__MACOSX", ".DS_Store", "desktop.ini", "Thumbs.db"]
+        .iter()
+        .any(|j| name == *j)
+}
+
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| !is_junk(p))
+        .collect()
+}
+
+// skip folders wrapping the whole package, e.g.) song.zip/song/song.bms
+fn strip_root(dir: &Path) -> PathBuf {
+    let mut dir = dir.to_path_buf();
+    loop {
+        match entries(&dir).as_slice() {
+            [only] if only.is_dir() => dir = only.clone(),
+            _ => return dir,
+        }
+    }
+}
+
+// folders having charts directly. their subfolders (e.g. bga/, sounds/) belong to them
+fn chart_folders(dir: &Path) -> Vec<PathBuf> {
+    let entries = entries(dir);
+    if entries.iter().any(|p| p.is_file() && filter_bms_files(p)) {
+        return vec![dir.to_path_buf()];
+    }
+    entries
+        .iter()
+        .filter(|p| p.is_dir())
+        .flat_map(|p| chart_folders(p))
+        .collect()
+}
+
+fn has_files(path: &Path) -> bool {
+    !path.is_dir() || entries(path).iter().any(|p| has_files(p))
+}
+
+// files of the song are kept if they have the same names
+fn copy_shared(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        for path in entries(from) {
+            copy_shared(&path, &to.join(path.file_name().unwrap_or_default()))?;
+        }
+    } else if to.exists() {
+        warn!("{:?} already exists", to);
+    } else {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+// a new song folder next to dest_dir, e.g.) <ts>-1
+fn new_song_dir(dest_dir: &Path, song_dirs: &mut Vec<PathBuf>) -> PathBuf {
+    let song_dir = dest_dir.with_file_name(format!(
+        "{}-{}",
+        dest_dir.file_name().unwrap_or_default().to_string_lossy(),
+        song_dirs.len()
+    ));
+    song_dirs.push(song_dir.clone());
+    song_dir
+}
+
+/// move a package into dest_dir, keeping its structure.
+/// if it has multiple songs or `separate` is set, each of them goes into a new song folder.
+fn place_package(
+    root: &Path,
+    dest_dir: &Path,
+    song_dirs: &mut Vec<PathBuf>,
+    separate: bool,
+) -> Result<()> {
+    let top = strip_root(root);
+    let folders = chart_folders(&top);
+    if separate && folders.len() == 1 {
+        let song_dir = new_song_dir(dest_dir, song_dirs);
+        fsutil::move_and_remove_dir(&top, &song_dir)?;
+    } else if folders.len() <= 1 {
+        if let Some(folder) = folders.first().filter(|f| **f != top) {
+            fsutil::move_and_remove_dir(folder, dest_dir)?;
+        }
+        fsutil::move_and_remove_dir(&top, dest_dir)?;
+    } else {
+        let mut split = vec![];
+        for folder in folders {
+            let song_dir = new_song_dir(dest_dir, song_dirs);
+            info!(
+                "split {:?} into {:?}",
+                folder.file_name().unwrap_or_default(),
+                song_dir
+            );
+            fsutil::move_and_remove_dir(&folder, &song_dir)?;
+            split.push(song_dir);
+        }
+        // files outside the songs, e.g.) readme or bga used by all of them
+        let shared: Vec<PathBuf> = entries(&top).into_iter().filter(|p| has_files(p)).collect();
+        if !shared.is_empty() {
+            info!(
+                "copy {:?} into each song",
+                shared
+                    .iter()
+                    .map(|p| p.file_name().unwrap_or_default())
+                    .collect::<Vec<_>>()
+            );
+            for song_dir in &split {
+                for path in &shared {
+                    copy_shared(path, &song_dir.join(path.file_name().unwrap_or_default()))?;
+                }
+            }
+        }
+    }
+    if root.exists() {
+        fs::remove_dir_all(root)?;
+    }
+    Ok(())
+}
+
+// charts whose sounds are not in their folders, i.e. diffs without the base song
+fn charts_without_sounds(installed: &InstalledCharts) -> Vec<(usize, Vec<String>)> {
+    installed
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (path, _))| {
+            let sounds = referenced_sounds(path)
+                .map_err(|e| warn!("failed to read {:?}: {}", path, e))
+                .ok()?;
+            let coverage = SoundFiles::new(path.parent()?).coverage(&sounds);
+            (!sounds.is_empty() && coverage < MIN_SOUND_COVERAGE).then_some((i, sounds))
+        })
+        .collect()
+}
+
+/// move diffs into the base song folders in mydir which have their sounds
+fn place_diffs(installed: &mut InstalledCharts, mydir: &Path) -> Result<()> {
+    let diffs = charts_without_sounds(installed);
+    if diffs.is_empty() {
+        return Ok(());
+    }
+    let base_folders = BaseFolders::new(&Library::open(mydir)?.charts()?);
+    if base_folders.is_empty() {
+        warn!("run scan to install diffs into their base song folders");
+        return Ok(());
+    }
+
+    for (i, sounds) in diffs {
+        let path = installed[i].0.clone();
+        let info = match ChartInfo::from_path(&path) {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("failed to read {:?}: {}", path, e);
+                continue;
+            }
+        };
+        let found = base_folders.find_by_sounds(
+            info.title.as_deref().unwrap_or(""),
+            info.artist.as_deref().unwrap_or(""),
+            &sounds,
+        );
+        let Some((folder, resolved)) = found else {
+            warn!("base song of {:?} is not found", path);
+            continue;
+        };
+        let dest = folder.join(path.file_name().unwrap_or_default());
+        if dest.exists() {
+            warn!("{:?} already exists", dest);
+            continue;
+        }
+        info!(
+            "install {:?} into {:?} ({} / {} sounds resolved)",
+            path.file_name().unwrap_or_default(),
+            folder,
+            resolved,
+            sounds.len()
+        );
+        match fsutil::move_file(&path, &dest) {
+            Ok(_) => installed[i].0 = dest,
+            Err(e) => {
+                warn!("failed to move {:?} into {:?}: {}", path, folder, e);
+                continue;
+            }
+        }
+        if let Some(dir) = path.parent() {
+            move_missing_sounds(&sounds, dir, folder);
+        }
+    }
+    Ok(())
+}
+
+// sounds shipped with the diff, which the base song folder doesn't have
+fn move_missing_sounds(sounds: &[String], dir: &Path, folder: &Path) {
+    let shipped = SoundFiles::new(dir);
+    let base = SoundFiles::new(folder);
+    for name in sounds.iter().filter(|n| !base.contains(n)) {
+        let Some(relative) = shipped.path(name) else {
+            continue;
+        };
+        let dest = folder.join(relative);
+        if dest.exists() {
+            continue;
+        }
+        debug!("install {:?} into {:?}", relative, folder);
+        dest.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fsutil::move_file(&dir.join(relative), &dest))
+            .unwrap_or_else(|e| warn!("failed to move {:?} into {:?}: {}", relative, folder, e));
+    }
+}
+
+pub fn install_from_dirs(
+    target_dir: &Path,
+    dest_dir: &Path,
+    hash_cache: &HashCache,
+    options: &InstallOptions,
+    dryrun: bool,
+) -> Result<Installation> {
+    let mut installation = Installation::default();
     let dirs: Vec<fs::DirEntry> = target_dir
         .read_dir()?
         .flatten()
-        .filter(|e| e.file_type().unwrap().is_dir())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
         .collect();
 
     for d in &dirs {
         info!("target_dir {:?}", d.file_name());
-        install_from_dir(&d.path(), dest_dir, dryrun)?;
-        // delete
-        if !dryrun {
+        match install_from_dir(&d.path(), dest_dir, hash_cache, options, dryrun) {
+            Ok(i) => installation.extend(i),
+            Err(e) => {
+                warn!("failed to install {:?}: {}", d.path(), e);
+                installation
+                    .failed
+                    .push((d.path(), ArchiveError::new(ErrorKind::Io, e)));
+                continue;
+            }
+        }
+        // remove if everything is installed
+        if !dryrun && !options.keep_source {
             fs::remove_dir(d.path()).unwrap_or_else(|e| warn!("failed to remove dir: {:?}", e));
         }
     }
 
+    Ok(installation)
+}
+
+// the extracted package goes into a song folder, which is renamed at last
+fn install_song_dir(
+    dir: &Path,
+    mydir: &Path,
+    hash_cache: &HashCache,
+    dryrun: bool,
+) -> Result<InstalledCharts> {
+    let mut installed = ChartHashes::new(dir, hash_cache)?.entries().to_vec();
+    place_diffs(&mut installed, mydir)?;
+    if !installed.is_empty() && installed.iter().all(|(path, _)| !path.starts_with(dir)) {
+        // every chart is moved into base song folders
+        if !has_files(dir) {
+            debug!("remove {:?}", dir);
+            fs::remove_dir_all(dir)?;
+            return Ok(installed);
+        }
+        // keep the rest of the package, e.g.) readme, next to the base song
+        let base = installed[0].0.parent().and_then(|p| p.file_name());
+        let dest = dir.with_file_name(format!(
+            "{} (diff)",
+            base.unwrap_or_default().to_string_lossy()
+        ));
+        if dest.exists() {
+            warn!("keep {:?}, as {:?} already exists", dir, dest);
+        } else {
+            info!("keep the rest of the diff package in {:?}", dest);
+            fsutil::move_and_remove_dir(dir, &dest)?;
+        }
+        return Ok(installed);
+    }
+
+    // rename
+    let renamed = rename_dirs(dir, hash_cache, dryrun)?;
+    for (path, _) in installed.iter_mut() {
+        let found = renamed
+            .iter()
+            .find_map(|(from, dest)| Some(dest.join(path.strip_prefix(from).ok()?)));
+        if let Some(dest) = found {
+            *path = dest;
+        }
+    }
+    Ok(installed)
+}
+
+// extract the archive and its nested archives into the dir
+fn extract_package(
+    path: &Path,
+    dir: &Path,
+    format: ArchiveFormat,
+    name_encoding: Option<&'static Encoding>,
+    options: &InstallOptions,
+) -> std::result::Result<(), ArchiveError> {
+    fs::create_dir_all(dir)?;
+    archive::extract(path, dir, format, name_encoding, &options.passwords)?;
+    extract_nested(dir, options.nest_depth, name_encoding, &options.passwords)
+        .map_err(|e| ArchiveError::new(ErrorKind::Io, e))?;
+    if chart_folders(dir).is_empty() {
+        return Err(ArchiveError::new(ErrorKind::NoCharts, "no charts found"));
+    }
+    Ok(())
+}
+
+// remove what the failed archive left, so that it doesn't mix with other packages
+fn rollback(extracted: &Path, new_dirs: &[PathBuf]) {
+    for dir in new_dirs.iter().chain([&extracted.to_path_buf()]) {
+        if dir.exists() {
+            debug!("remove {:?}", dir);
+            fs::remove_dir_all(dir).unwrap_or_else(|e| warn!("failed to remove {:?}: {}", dir, e));
+        }
+    }
+}
+
+// files and folders in the dir, recursively
+fn paths_in(dir: &Path, paths: &mut HashSet<PathBuf>) {
+    for path in fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+    {
+        if path.is_dir() {
+            paths_in(&path, paths);
+        }
+        paths.insert(path);
+    }
+}
+
+// remove what the failed archive added to the dir, which has other packages
+fn remove_added(dir: &Path, before: &HashSet<PathBuf>) {
+    let mut after = HashSet::new();
+    paths_in(dir, &mut after);
+    let added = after.difference(before).filter(|path| {
+        path.parent()
+            .is_some_and(|parent| parent == dir || before.contains(parent))
+    });
+    for path in added {
+        debug!("remove {:?}", path);
+        let removed = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+        removed.unwrap_or_else(|e| warn!("failed to remove {:?}: {}", path, e));
+    }
+}
+
+// keep the source in processed/ or failed/ instead of deleting it
+fn move_source(path: &Path, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let dest = dir.join(path.file_name().unwrap_or_default());
+    info!("move {:?} to {:?}", path, dest);
+    fsutil::move_file(path, &dest)?;
+    Ok(())
+}
+
+// folders get their own song folders, while charts (i.e. diffs) go into dest_dir
+fn place_loose(
+    path: &Path,
+    copied: &Path,
+    dest_dir: &Path,
+    song_dirs: &mut Vec<PathBuf>,
+    options: &InstallOptions,
+) -> Result<()> {
+    if path.is_dir() {
+        if options.keep_source {
+            fsutil::copy_dir(path, copied)?;
+            place_package(copied, dest_dir, song_dirs, true)?;
+        } else {
+            place_package(path, dest_dir, song_dirs, true)?;
+        }
+    } else {
+        fs::create_dir_all(dest_dir)?;
+        let dest = dest_dir.join(path.file_name().unwrap_or_default());
+        if options.keep_source {
+            fs::copy(path, dest)?;
+        } else {
+            fsutil::move_file(path, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+// charts placed with their sounds, e.g.) a folder of files downloaded from a song page
+fn has_charts_with_sounds(dir: &Path) -> bool {
+    let files = SoundFiles::new(dir);
+    entries(dir)
+        .iter()
+        .filter(|p| p.is_file() && filter_bms_files(p))
+        .any(|chart| {
+            referenced_sounds(chart).is_ok_and(|sounds| {
+                !sounds.is_empty() && files.coverage(&sounds) >= MIN_SOUND_COVERAGE
+            })
+        })
+}
+
+// the files in the dir go into a new song folder together, leaving the dir itself
+fn place_song_files(
+    dir: &Path,
+    skipped: &[PathBuf],
+    dest_dir: &Path,
+    song_dirs: &mut Vec<PathBuf>,
+    options: &InstallOptions,
+) -> Result<()> {
+    let song_dir = new_song_dir(dest_dir, song_dirs);
+    fs::create_dir_all(&song_dir)?;
+    for path in entries(dir).iter().filter(|p| !skipped.contains(p)) {
+        let dest = song_dir.join(path.file_name().unwrap_or_default());
+        match (path.is_dir(), options.keep_source) {
+            (true, true) => fsutil::copy_dir(path, &dest)?,
+            (true, false) => fsutil::move_and_remove_dir(path, &dest)?,
+            (false, true) => fs::copy(path, &dest).map(|_| ())?,
+            (false, false) => fsutil::move_file(path, &dest)?,
+        }
+    }
     Ok(())
 }
 
-pub fn install_from_dir(target_dir: &Path, dest_dir: &Path, dryrun: bool) -> Result<()> {
+pub fn install_from_dir(
+    target_dir: &Path,
+    dest_dir: &Path,
+    hash_cache: &HashCache,
+    options: &InstallOptions,
+    dryrun: bool,
+) -> Result<Installation> {
     // サブフォルダを対象ディレクトリに追加
     let utc: DateTime<Utc> = Utc::now();
     let format = "%s%6f";
     let ts = utc.format(format).to_string();
 
-    let dest_dir = dest_dir.join(ts);
+    let mydir = dest_dir;
+    let dest_dir = dest_dir.join(&ts);
+    let staging_dir = fsutil::app_dir(mydir).join(STAGING_DIR_NAME).join(&ts);
+    let name_encoding = encoding::folder_encoding(target_dir);
+    // songs split from packages go next to dest_dir
+    let mut song_dirs = vec![dest_dir.clone()];
+    let mut failed = vec![];
 
-    // lookup zip files
-    let zips: Vec<fs::DirEntry> = target_dir
+    // lookup archives
+    let zips: Vec<(fs::DirEntry, ArchiveFormat)> = target_dir
         .read_dir()?
         .flatten()
-        .filter(|e| {
-            let path = e.path();
-            let ext = path.extension();
-            ext == Some(OsStr::new("zip")) || ext == Some(OsStr::new("rar"))
-        })
+        .filter(|e| e.path().is_file())
+        .flat_map(|e| ArchiveFormat::detect(&e.path()).map(|f| (e, f)))
         .collect();
 
-    for zip_file in zips {
+    for (i, (zip_file, format)) in zips.iter().enumerate() {
         info!(
             "extracting: source:{:?} , dest:{:?}",
             zip_file.file_name(),
@@ -57,71 +611,114 @@ pub fn install_from_dir(target_dir: &Path, dest_dir: &Path, dryrun: bool) -> Res
             continue;
         }
 
-        match zip_file.path().extension() {
-            None => continue,
-            Some(os_str) => match os_str.to_str() {
-                Some("zip") => {
-                    let file = fs::File::open(zip_file.path()).unwrap();
-                    let mut archive = zip::ZipArchive::new(file).unwrap();
-
-                    for i in 0..archive.len() {
-                        let mut file = archive.by_index(i)?;
-
-                        // UTF-8かSJISかを判定する
-                        let sjis_name = encoding_rs::SHIFT_JIS.decode(file.name_raw()).0;
-                        let archived_file_name =
-                            str::from_utf8(file.name_raw()).unwrap_or(&sjis_name);
-
-                        let file_name = Path::new(archived_file_name).file_name().unwrap();
-                        let t = &dest_dir.join(Path::new(file_name));
-
-                        t.parent().map(fs::create_dir_all);
-                        let mut output = fs::File::create(t)
-                            .unwrap_or_else(|_| panic!("Failed to create file: {:?}", t));
-                        std::io::copy(&mut file, &mut output)?;
-                    }
-                }
-                Some("rar") => {
-                    let mut archive = Archive::new(&zip_file.path())
-                        .open_for_processing()
-                        .unwrap();
-                    while let Some(header) = archive.read_header()? {
-                        debug!(
-                            "{} bytes: {}",
-                            header.entry().unpacked_size,
-                            header.entry().filename.to_string_lossy(),
-                        );
-                        archive = if header.entry().is_file() {
-                            let archived_path = header.entry().filename.file_name();
-                            match archived_path {
-                                Some(n) => {
-                                    let t = dest_dir.join(Path::new(n));
-                                    header.extract_to(t)?
-                                }
-                                None => {
-                                    warn!(
-                                        "Invalid file {:?} in {:?}",
-                                        &header.entry().filename,
-                                        zip_file.path()
-                                    );
-                                    header.skip()?
-                                }
-                            }
-                        } else {
-                            header.skip()?
-                        };
-                    }
+        let path = zip_file.path();
+        let extracted = staging_dir.join(i.to_string());
+        // song folders made by this archive
+        let song_dirs_len = song_dirs.len();
+        let new_dest_dir = !dest_dir.exists();
+        let mut before = HashSet::new();
+        paths_in(&dest_dir, &mut before);
+        let outcome =
+            extract_package(&path, &extracted, *format, name_encoding, options).and_then(|_| {
+                place_package(&extracted, &dest_dir, &mut song_dirs, false)
+                    .map_err(|e| ArchiveError::new(ErrorKind::Io, e))
+            });
+        let moved_to = match outcome {
+            Ok(_) => PROCESSED_DIR_NAME,
+            // a file which happens to be in an archive format, e.g.) a document
+            Err(e) if e.kind == ErrorKind::NoCharts && !archive::has_archive_extension(&path) => {
+                info!("skip {:?}: it's not a package", path);
+                rollback(&extracted, &song_dirs.split_off(song_dirs_len));
+                continue;
+            }
+            Err(e) => {
+                warn!("failed to install {:?}: {}", path, e);
+                let mut new_dirs = song_dirs.split_off(song_dirs_len);
+                if new_dest_dir {
+                    new_dirs.push(dest_dir.clone());
+                } else {
+                    remove_added(&dest_dir, &before);
                 }
-                _ => panic!("unknown extension"),
-            },
+                rollback(&extracted, &new_dirs);
+                failed.push((path.clone(), e));
+                FAILED_DIR_NAME
+            }
         };
+        if !options.keep_source {
+            move_source(&path, &fsutil::app_dir(mydir).join(moved_to).join(&ts))
+                .unwrap_or_else(|e| warn!("failed to move {:?}: {}", path, e));
+        }
+    }
 
-        // delete
-        fs::remove_file(zip_file.path())?;
+    let is_song = has_charts_with_sounds(target_dir);
+    if is_song {
+        // the dir is a song itself, so its charts are not split from the sounds
+        info!(
+            "moving: source:{:?} as a song, dest:{:?}",
+            target_dir, dest_dir
+        );
+        if !dryrun {
+            let archives: Vec<PathBuf> = zips.iter().map(|(e, _)| e.path()).collect();
+            let placed =
+                place_song_files(target_dir, &archives, &dest_dir, &mut song_dirs, options);
+            if let Err(e) = placed {
+                warn!("failed to install {:?}: {}", target_dir, e);
+                failed.push((
+                    target_dir.to_path_buf(),
+                    ArchiveError::new(ErrorKind::Io, e),
+                ));
+            }
+        }
     }
 
-    // rename
-    rename_dirs(&dest_dir, dryrun)?;
+    // loose charts and folders containing charts, such as a downloaded diff
+    let loose: Vec<PathBuf> = target_dir
+        .read_dir()?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|_| !is_song)
+        .filter(|path| {
+            if path.is_dir() {
+                !chart_folders(path).is_empty()
+            } else {
+                filter_bms_files(path)
+            }
+        })
+        .collect();
+    for (i, path) in loose.iter().enumerate() {
+        info!(
+            "moving: source:{:?} , dest:{:?}",
+            path.file_name().unwrap_or_default(),
+            dest_dir
+        );
+        if dryrun {
+            continue;
+        }
+        let copied = staging_dir.join(format!("loose-{}", i));
+        if let Err(e) = place_loose(path, &copied, &dest_dir, &mut song_dirs, options) {
+            warn!("failed to install {:?}: {}", path, e);
+            failed.push((path.clone(), ArchiveError::new(ErrorKind::Io, e)));
+        }
+    }
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)
+            .unwrap_or_else(|e| warn!("failed to remove {:?}: {}", staging_dir, e));
+        if let Some(parent) = staging_dir.parent() {
+            // fails if other installs are running
+            fs::remove_dir(parent).unwrap_or_else(|e| debug!("{:?}: {}", parent, e));
+        }
+    }
 
-    Ok(())
+    let mut installed = vec![];
+    for dir in song_dirs.iter().filter(|d| d.exists()) {
+        // the other songs are installed even if one fails
+        match install_song_dir(dir, mydir, hash_cache, dryrun) {
+            Ok(charts) => installed.extend(charts),
+            Err(e) => {
+                warn!("failed to install {:?}: {}", dir, e);
+                failed.push((dir.clone(), ArchiveError::new(ErrorKind::Io, e)));
+            }
+        }
+    }
+    Ok(Installation { installed, failed })
 }
@@ -9,6 +9,7 @@ use std::path::Path;
 use crate::chart_hashes::ChartHashes;
 use crate::errors::Result;
 use crate::fsutil;
+use crate::hash_cache::HashCache;
 
 const MERGE_THRESHOLD: u8 = 80;
 
@@ -37,8 +38,8 @@ fn is_duplicated(path1: &Path, path2: &Path, threshold: Option<u8>) -> Result<bo
     Ok(dupe_confidence >= threshold.into())
 }
 
-pub fn merge(current_dir: &Path, dryrun: bool) -> Result<()> {
-    let chart_hashes = ChartHashes::new(current_dir)?;
+pub fn merge(current_dir: &Path, hash_cache: &HashCache, dryrun: bool) -> Result<()> {
+    let chart_hashes = ChartHashes::new(current_dir, hash_cache)?;
 
     let mut merge_targets: HashMap<&Path, &Path> = HashMap::new();
     for v in chart_hashes.hashes().values() {
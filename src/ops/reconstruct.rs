@@ -1,6 +1,7 @@
 use crate::chart_hashes::ChartHashes;
 use crate::errors::Result;
 use crate::fsutil;
+use crate::hash_cache::HashCache;
 use log::info;
 use md5::{Digest, Md5};
 use std::fmt::Write;
@@ -15,8 +16,14 @@ fn hex_encode(bytes: &[u8]) -> String {
     })
 }
 
-pub fn reconstruct(current_dir: &Path, dest_dir: &Path, dryrun: bool, shard: bool) -> Result<()> {
-    let chart_hashes = ChartHashes::new(current_dir)?;
+pub fn reconstruct(
+    current_dir: &Path,
+    dest_dir: &Path,
+    hash_cache: &HashCache,
+    dryrun: bool,
+    shard: bool,
+) -> Result<()> {
+    let chart_hashes = ChartHashes::new(current_dir, hash_cache)?;
     let parents = chart_hashes.parents()?;
 
     for d in &parents {
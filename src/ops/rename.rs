@@ -1,36 +1,16 @@
 use crate::chart_hashes::{filter_bms_files, ChartHashes};
+use crate::chart_info::ChartInfo;
 use crate::errors::Result;
 use crate::fsutil;
+use crate::hash_cache::HashCache;
+use crate::utils::remove_difficulty;
 use log::warn;
 use log::{debug, info};
 use rayon::prelude::*;
-use regex::Regex;
 use std::ffi::OsString;
-use std::fs;
-use std::io::{self, BufRead};
 use std::path::Path;
 use std::path::PathBuf;
 
-fn read_artist_and_title(lines: Vec<String>) -> Result<(Option<String>, Option<String>)> {
-    let mut artist = None;
-    let mut title = None;
-
-    for l in lines {
-        // filter title, artist
-        if let Some(t) = l.strip_prefix("#ARTIST ") {
-            artist = Some(String::from(t))
-        }
-        if let Some(t) = l.strip_prefix("#TITLE ") {
-            title = Some(String::from(t));
-        }
-        if artist.is_some() && title.is_some() {
-            break;
-        }
-    }
-
-    Ok((artist, title))
-}
-
 fn sanitize_str(s: &str, n: usize) -> String {
     // replace (possibly) invalid path string
     let s = ["/", "\"", "?", "<", ">", "*", ":", "|", "."]
@@ -48,26 +28,9 @@ where
     T: Iterator<Item = PathBuf>,
 {
     charts_paths.map(|path| {
-        let file = fs::File::open(&path)?;
-        let lines = io::BufReader::new(file)
-            .lines()
-            .map_while(|x| x.ok())
-            .collect::<Vec<String>>();
-        let (artist1, title1) = read_artist_and_title(lines)?;
-
-        // if one of info is None, read again in SHIFT-JIS
-        let (artist2, title2) = if artist1.is_none() || title1.is_none() {
-            let bytes = fs::read(&path)?;
-            let (res, enc, b) = encoding_rs::SHIFT_JIS.decode(&bytes);
-            debug!("{:?} {:?}", enc, b);
-            let lines = res.into_owned().lines().map(String::from).collect();
-            read_artist_and_title(lines)?
-        } else {
-            (None, None)
-        };
-
-        let artist = artist1.or(artist2);
-        let title = title1.or(title2);
+        let info = ChartInfo::from_path(&path)?;
+        let artist = info.artist;
+        let title = info.title;
         debug!("{:?}, {:?}, {:?}", &path, artist, title);
 
         if let (Some(a), Some(t)) = (&artist, &title) {
@@ -84,16 +47,6 @@ where
     })
 }
 
-// if the name has '[.*]' and other strings, remove it
-fn remove_difficulty(name: &str) -> String {
-    let re = Regex::new(r"(?P<name>.+?)(?P<diff>\s*\[.*?\])\s*$").unwrap();
-    let caps = re.captures(name);
-    debug!("{:?}", caps);
-    caps.and_then(|cap| cap.name("name").map(|e| e.as_str()))
-        .unwrap_or(name)
-        .to_owned()
-}
-
 fn read_files_and_name(dir: &Path) -> Option<OsString> {
     let charts_paths = dir
         .read_dir()
@@ -128,8 +81,13 @@ fn read_files_and_name(dir: &Path) -> Option<OsString> {
     }
 }
 
-pub fn rename_dirs(current_dir: &Path, dryrun: bool) -> Result<()> {
-    let chart_hashes = ChartHashes::new(current_dir)?;
+/// returns renamed directories
+pub fn rename_dirs(
+    current_dir: &Path,
+    hash_cache: &HashCache,
+    dryrun: bool,
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let chart_hashes = ChartHashes::new(current_dir, hash_cache)?;
     let parents = chart_hashes.parents()?;
 
     // process in parallel, but rename sequentially
@@ -146,6 +104,7 @@ pub fn rename_dirs(current_dir: &Path, dryrun: bool) -> Result<()> {
         })
         .collect_into_vec(&mut rename_targets);
 
+    let mut renamed = vec![];
     rename_targets
         .into_iter()
         .flatten()
@@ -155,11 +114,13 @@ pub fn rename_dirs(current_dir: &Path, dryrun: bool) -> Result<()> {
                 if dest.exists() {
                     warn!("rename cancelled. destination already exists. {:?}", dest);
                 } else if !dryrun {
-                    fsutil::move_and_remove_dir(from, &dest)
-                        .unwrap_or_else(|_| warn!("rename failed. {:?} -> {:?}", from, dest))
+                    match fsutil::move_and_remove_dir(from, &dest) {
+                        Ok(_) => renamed.push((from.to_path_buf(), dest)),
+                        Err(_) => warn!("rename failed. {:?} -> {:?}", from, dest),
+                    }
                 }
             }
         });
 
-    Ok(())
+    Ok(renamed)
 }
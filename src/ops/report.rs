@@ -0,0 +1,300 @@
+use crate::chart_hashes::ChartHashes;
+use crate::errors::Result;
+use crate::hash_cache::HashCache;
+use crate::library::Library;
+use crate::table_cache::TableCache;
+use crate::table_loader::TableLoader;
+use crate::table_registry::TableRegistry;
+use crate::FrontendMsg;
+use html_escape::encode_text;
+use log::info;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::ArgEnum, Clone, Copy, Debug)]
+pub enum ReportFormat {
+    Terminal,
+    Csv,
+    Json,
+    Html,
+}
+
+#[derive(Serialize, Debug)]
+struct LevelCoverage {
+    pub level: String,
+    pub owned: usize,
+    pub total: usize,
+    pub percent: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct TableCoverage {
+    pub symbol: String,
+    pub name: String,
+    pub levels: Vec<LevelCoverage>,
+    pub owned: usize,
+    pub total: usize,
+    pub percent: f64,
+}
+
+#[derive(Serialize, Debug)]
+struct CoverageReport {
+    pub tables: Vec<TableCoverage>,
+    pub owned: usize,
+    pub total: usize,
+    pub percent: f64,
+}
+
+fn percent(owned: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        owned as f64 * 100.0 / total as f64
+    }
+}
+
+fn table_coverage(symbol: &str, table: &TableLoader, chart_hashes: &ChartHashes) -> TableCoverage {
+    let levels: Vec<LevelCoverage> = table
+        .levels()
+        .iter()
+        .map(|level| {
+            let charts: Vec<_> = table
+                .charts()
+                .iter()
+                .filter(|sd| sd.level == level.name())
+                .collect();
+            let owned = charts
+                .iter()
+                .filter(|sd| chart_hashes.find(&sd.sha256, &sd.md5).is_some())
+                .count();
+            LevelCoverage {
+                level: level.name().to_owned(),
+                owned,
+                total: charts.len(),
+                percent: percent(owned, charts.len()),
+            }
+        })
+        // levels only in level_order
+        .filter(|l| l.total > 0)
+        .collect();
+
+    let owned = levels.iter().map(|l| l.owned).sum();
+    let total = levels.iter().map(|l| l.total).sum();
+    TableCoverage {
+        symbol: symbol.to_owned(),
+        name: table.header().name.clone(),
+        levels,
+        owned,
+        total,
+        percent: percent(owned, total),
+    }
+}
+
+fn bar(percent: f64) -> String {
+    let filled = (percent / 5.0).round() as usize;
+    format!("{}{}", "#".repeat(filled), ".".repeat(20 - filled))
+}
+
+fn render_terminal(report: &CoverageReport) -> String {
+    let mut out = String::new();
+    for t in &report.tables {
+        out += &format!("{} {}\n", t.symbol, t.name);
+        for l in &t.levels {
+            out += &format!(
+                "  {:>8} {:>5} / {:<5} {:>5.1}% {}\n",
+                format!("{}{}", t.symbol, l.level),
+                l.owned,
+                l.total,
+                l.percent,
+                bar(l.percent)
+            );
+        }
+        out += &format!(
+            "  {:>8} {:>5} / {:<5} {:>5.1}%\n\n",
+            "total", t.owned, t.total, t.percent
+        );
+    }
+    out += "summary\n";
+    for t in &report.tables {
+        out += &format!(
+            "  {:>8} {:>5} / {:<5} {:>5.1}% {}\n",
+            t.symbol,
+            t.owned,
+            t.total,
+            t.percent,
+            bar(t.percent)
+        );
+    }
+    out += &format!(
+        "  {:>8} {:>5} / {:<5} {:>5.1}%\n",
+        "total", report.owned, report.total, report.percent
+    );
+    out
+}
+
+fn render_csv(report: &CoverageReport) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(["table", "name", "level", "owned", "total", "percent"])?;
+    for t in &report.tables {
+        let rows = t
+            .levels
+            .iter()
+            .map(|l| (l.level.as_str(), l.owned, l.total, l.percent))
+            .chain([("total", t.owned, t.total, t.percent)]);
+        for (level, owned, total, pct) in rows {
+            writer.write_record([
+                t.symbol.as_str(),
+                t.name.as_str(),
+                level,
+                &owned.to_string(),
+                &total.to_string(),
+                &format!("{:.1}", pct),
+            ])?;
+        }
+    }
+    writer.write_record([
+        "total",
+        "",
+        "total",
+        &report.owned.to_string(),
+        &report.total.to_string(),
+        &format!("{:.1}", report.percent),
+    ])?;
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+fn html_row(label: &str, owned: usize, total: usize, pct: f64) -> String {
+    format!(
+        "<tr><th>{}</th><td>{} / {}</td><td>{:.1}%</td><td><div class=\"bar\"><div style=\"width: {:.1}%\"></div></div></td></tr>\n",
+        encode_text(label),
+        owned,
+        total,
+        pct,
+        pct
+    )
+}
+
+fn render_html(report: &CoverageReport) -> String {
+    let mut body = String::new();
+    body += "<h2>summary</h2>\n<table>\n";
+    for t in &report.tables {
+        body += &html_row(
+            &format!("{} {}", t.symbol, t.name),
+            t.owned,
+            t.total,
+            t.percent,
+        );
+    }
+    body += &html_row("total", report.owned, report.total, report.percent);
+    body += "</table>\n";
+
+    for t in &report.tables {
+        body += &format!(
+            "<h2>{} {}</h2>\n<table>\n",
+            encode_text(&t.symbol),
+            encode_text(&t.name)
+        );
+        for l in &t.levels {
+            body += &html_row(
+                &format!("{}{}", t.symbol, l.level),
+                l.owned,
+                l.total,
+                l.percent,
+            );
+        }
+        body += &html_row("total", t.owned, t.total, t.percent);
+        body += "</table>\n";
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>beatman coverage report</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+th, td {{ padding: 2px 12px; text-align: right; }}
+.bar {{ width: 200px; height: 12px; background: #eee; }}
+.bar div {{ height: 100%; background: #4a8; }}
+</style>
+</head>
+<body>
+<h1>coverage report</h1>
+{}</body>
+</html>
+"#,
+        body
+    )
+}
+
+/// owned / total charts per level of every registered table
+pub fn coverage_report(
+    mydir: &Path,
+    hash_cache: &HashCache,
+    table_cache: &TableCache,
+    symbols: &[String],
+    use_index: bool,
+    format: ReportFormat,
+    output: &Option<PathBuf>,
+) -> Result<()> {
+    let registry = TableRegistry::open(mydir)?;
+    let targets: Vec<(String, String)> = if symbols.is_empty() {
+        registry
+            .tables()
+            .iter()
+            .map(|t| (t.symbol.clone(), t.url.clone()))
+            .collect()
+    } else {
+        symbols
+            .iter()
+            .map(|s| match registry.find(s) {
+                Some(t) => (t.symbol.clone(), t.url.clone()),
+                None => (s.clone(), s.clone()),
+            })
+            .collect()
+    };
+    if targets.is_empty() {
+        return Err("no table is registered. add tables by the table add command".into());
+    }
+
+    let chart_hashes = if use_index {
+        Library::open(mydir)?.chart_hashes()?
+    } else {
+        ChartHashes::new(mydir, hash_cache)?
+    };
+
+    let tables = targets
+        .iter()
+        .map(|(symbol, url)| {
+            let table = TableLoader::new(url, table_cache)?;
+            Ok(table_coverage(symbol, &table, &chart_hashes))
+        })
+        .collect::<Result<Vec<TableCoverage>>>()?;
+    let owned = tables.iter().map(|t| t.owned).sum();
+    let total = tables.iter().map(|t| t.total).sum();
+    let report = CoverageReport {
+        tables,
+        owned,
+        total,
+        percent: percent(owned, total),
+    };
+    info!(target: &FrontendMsg::CoverageReport.to_string(), "{}", serde_json::to_string(&report)?);
+
+    let rendered = match format {
+        ReportFormat::Terminal => render_terminal(&report),
+        ReportFormat::Csv => render_csv(&report)?,
+        ReportFormat::Json => serde_json::to_string_pretty(&report)?,
+        ReportFormat::Html => render_html(&report),
+    };
+    match output {
+        Some(path) => {
+            fs::write(path, rendered)?;
+            info!("the report is written to {:?}", path);
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use crate::chart_hashes::ChartHashes;
+use crate::chart_info::ChartInfo;
+use crate::errors::Result;
+use crate::hash_cache::HashCache;
+use crate::library::{IndexedChart, Library};
+use log::{info, warn};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// jwalk is not used here since it may deadlock inside the rayon pool
+fn folder_size(dir: &Path) -> u64 {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| match e.file_type() {
+            Ok(t) if t.is_dir() => folder_size(&e.path()),
+            Ok(_) => e.metadata().map(|m| m.len()).unwrap_or(0),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+pub fn scan(mydir: &Path, hash_cache: &HashCache, full: bool) -> Result<()> {
+    let chart_hashes = ChartHashes::new(mydir, hash_cache)?;
+    let mut library = Library::open(mydir)?;
+    let indexed = library.stats()?;
+
+    // only new or modified charts are parsed
+    let targets: Vec<_> = chart_hashes
+        .entries()
+        .iter()
+        .filter(|(path, c)| full || indexed.get(path) != Some(&(c.size, c.mtime)))
+        .collect();
+    info!("indexing {:?} charts", targets.len());
+
+    let folders: HashSet<&Path> = targets.iter().flat_map(|(p, _)| p.parent()).collect();
+    let folder_sizes: HashMap<&Path, u64> = folders
+        .into_par_iter()
+        .map(|d| (d, folder_size(d)))
+        .collect();
+
+    let charts: Vec<IndexedChart> = targets
+        .par_iter()
+        .flat_map(|(path, c)| {
+            let info = ChartInfo::from_path(path)
+                .map_err(|e| warn!("failed to parse {:?}: {}", path, e))
+                .ok()?;
+            let folder = path.parent().unwrap_or(mydir);
+            Some(IndexedChart {
+                path: path.clone(),
+                folder: folder.to_path_buf(),
+                sha256: c.sha256.clone(),
+                md5: c.md5.clone(),
+                title: info.title,
+                subtitle: info.subtitle,
+                artist: info.artist,
+                subartist: info.subartist,
+                genre: info.genre,
+                level: info.level,
+                notes: info.notes as u32,
+                min_bpm: info.timing.map(|t| t.min_bpm),
+                max_bpm: info.timing.map(|t| t.max_bpm),
+                length: info.timing.map(|t| t.length_secs),
+                size: c.size,
+                folder_size: folder_sizes.get(folder).copied().unwrap_or(0),
+                mtime: c.mtime,
+            })
+        })
+        .collect();
+    library.upsert(&charts)?;
+
+    let found: HashSet<&PathBuf> = chart_hashes.entries().iter().map(|(p, _)| p).collect();
+    let removed: Vec<PathBuf> = indexed.into_keys().filter(|p| !found.contains(p)).collect();
+    library.remove(&removed)?;
+
+    info!(
+        "indexed {:?} charts, removed {:?} charts, {:?} charts in total",
+        charts.len(),
+        removed.len(),
+        found.len()
+    );
+
+    Ok(())
+}
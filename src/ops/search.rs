@@ -0,0 +1,100 @@
+use crate::errors::Result;
+use crate::library::{IndexedChart, Library};
+use crate::utils::normalize_name;
+use crate::FrontendMsg;
+use log::{info, warn};
+use std::collections::HashSet;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub genre: Option<String>,
+    pub hash: Option<String>,
+    pub level_min: Option<f64>,
+    pub level_max: Option<f64>,
+}
+
+// 0: no match, larger is better
+fn fuzzy_score(haystack: &Option<String>, needle: &str) -> u8 {
+    let haystack = normalize_name(haystack.as_deref().unwrap_or(""));
+    let needle = normalize_name(needle);
+    if haystack == needle {
+        3
+    } else if haystack.starts_with(&needle) {
+        2
+    } else if haystack.contains(&needle) {
+        1
+    } else {
+        0
+    }
+}
+
+fn level_of(chart: &IndexedChart) -> Option<f64> {
+    chart.level.as_ref().and_then(|l| l.trim().parse().ok())
+}
+
+fn score(chart: &IndexedChart, query: &SearchQuery) -> Option<u8> {
+    let mut total = 0;
+    for (field, needle) in [(&chart.title, &query.title), (&chart.artist, &query.artist)] {
+        if let Some(n) = needle {
+            match fuzzy_score(field, n) {
+                0 => return None,
+                s => total += s,
+            }
+        }
+    }
+    if let Some(g) = &query.genre {
+        let genre = chart.genre.as_deref().unwrap_or("").to_lowercase();
+        if !genre.contains(&g.to_lowercase()) {
+            return None;
+        }
+    }
+    if query.level_min.is_some() || query.level_max.is_some() {
+        let level = level_of(chart)?;
+        if query.level_min.is_some_and(|l| level < l) || query.level_max.is_some_and(|l| level > l)
+        {
+            return None;
+        }
+    }
+    Some(total)
+}
+
+pub fn search(mydir: &Path, query: &SearchQuery) -> Result<()> {
+    let library = Library::open(mydir)?;
+    let candidates = match &query.hash {
+        Some(h) => library.find_by_hash(h, h)?,
+        None => library.charts()?,
+    };
+    if candidates.is_empty() {
+        warn!("no chart is indexed. run scan first");
+    }
+
+    let mut results: Vec<(u8, IndexedChart)> = candidates
+        .into_iter()
+        .flat_map(|c| score(&c, query).map(|s| (s, c)))
+        .collect();
+    // better matches first
+    results.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.path.cmp(&b.1.path)));
+
+    for (_, c) in &results {
+        info!(
+            "[{}] {} / {} -- {:?}",
+            c.level.as_deref().unwrap_or("?"),
+            c.title.as_deref().unwrap_or(""),
+            c.artist.as_deref().unwrap_or(""),
+            c.folder
+        );
+        info!(target: &FrontendMsg::SearchResult.to_string(), "{}", serde_json::to_string(c)?);
+    }
+
+    let folders: HashSet<&Path> = results.iter().map(|(_, c)| c.folder.as_path()).collect();
+    info!(
+        "{} charts found in {} folders",
+        results.len(),
+        folders.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use crate::errors::Result;
+use crate::table_cache::TableCache;
+use crate::table_loader::{self, ScoreData, TableLoader};
+use crate::table_registry::{RegisteredTable, TableRegistry};
+use crate::FrontendMsg;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Serialize, Debug)]
+struct TableEntry {
+    pub symbol: String,
+    pub name: String,
+    pub url: String,
+    pub charts: Option<usize>,
+    pub fetched_at: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct LevelChange {
+    pub title: String,
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct TableUpdate {
+    pub symbol: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub level_changed: Vec<LevelChange>,
+}
+
+// some tables have md5 only. none for entries without any of them
+fn chart_key(sd: &ScoreData) -> Option<String> {
+    [&sd.sha256, &sd.md5, &sd.title]
+        .into_iter()
+        .find(|k| !k.is_empty())
+        .map(|k| k.to_lowercase())
+}
+
+fn diff(symbol: &str, old: &TableLoader, new: &TableLoader) -> TableUpdate {
+    let old_charts: HashMap<String, &ScoreData> = old
+        .charts()
+        .iter()
+        .flat_map(|sd| Some((chart_key(sd)?, sd)))
+        .collect();
+    let new_charts: HashMap<String, &ScoreData> = new
+        .charts()
+        .iter()
+        .flat_map(|sd| Some((chart_key(sd)?, sd)))
+        .collect();
+
+    let mut update = TableUpdate {
+        symbol: symbol.to_owned(),
+        ..Default::default()
+    };
+    for sd in new.charts() {
+        let Some(key) = chart_key(sd) else { continue };
+        match old_charts.get(&key) {
+            None => update.added.push(format!("[{}] {}", sd.level, sd.title)),
+            Some(o) if o.level != sd.level => update.level_changed.push(LevelChange {
+                title: sd.title.clone(),
+                from: o.level.clone(),
+                to: sd.level.clone(),
+            }),
+            _ => {}
+        }
+    }
+    for sd in old.charts() {
+        let Some(key) = chart_key(sd) else { continue };
+        if !new_charts.contains_key(&key) {
+            update.removed.push(format!("[{}] {}", sd.level, sd.title));
+        }
+    }
+    update
+}
+
+pub fn add_table(
+    mydir: &Path,
+    table_cache: &TableCache,
+    url: &str,
+    symbol: &Option<String>,
+) -> Result<()> {
+    let mut registry = TableRegistry::open(mydir)?;
+    // local paths are registered as absolute file:// urls
+    let url = table_loader::source_url(url)?.to_string();
+    let table = TableLoader::new(&url, table_cache)?;
+    let header = table.header();
+    let symbol = match symbol {
+        Some(s) => s.clone(),
+        None if !header.symbol.is_empty() => header.symbol.clone(),
+        None => return Err(format!("{} has no symbol. specify it by --symbol", url).into()),
+    };
+    if let Some(t) = registry.tables().iter().find(|t| t.symbol == symbol) {
+        if t.url != url {
+            warn!("replace {} ({}) registered as {:?}", t.name, t.url, symbol);
+        }
+    }
+
+    info!(
+        "add {} as {:?}: {} charts",
+        header.name,
+        symbol,
+        table.charts().len()
+    );
+    registry.insert(RegisteredTable {
+        symbol,
+        name: header.name.clone(),
+        url,
+    });
+    registry.save()
+}
+
+pub fn update_tables(mydir: &Path, table_cache: &TableCache, symbols: &[String]) -> Result<()> {
+    let registry = TableRegistry::open(mydir)?;
+    let targets: Vec<&RegisteredTable> = if symbols.is_empty() {
+        registry.tables().iter().collect()
+    } else {
+        symbols
+            .iter()
+            .map(|s| {
+                registry
+                    .find(s)
+                    .ok_or_else(|| format!("{:?} is not registered", s).into())
+            })
+            .collect::<Result<_>>()?
+    };
+
+    for t in targets {
+        let old = table_cache.load(&t.url)?;
+        let new = TableLoader::new(&t.url, table_cache)?;
+        let old = match old {
+            Some(old) if old.fetched_at() == new.fetched_at() => {
+                info!("{}: not updated", t.symbol);
+                continue;
+            }
+            Some(old) => old,
+            None => {
+                info!("{}: {} charts", t.symbol, new.charts().len());
+                continue;
+            }
+        };
+
+        let update = diff(&t.symbol, &old, &new);
+        for a in &update.added {
+            info!("{}: added {}", t.symbol, a);
+        }
+        for r in &update.removed {
+            info!("{}: removed {}", t.symbol, r);
+        }
+        for c in &update.level_changed {
+            info!("{}: {} {} -> {}", t.symbol, c.title, c.from, c.to);
+        }
+        info!(
+            "{}: {} added, {} removed, {} level changed",
+            t.symbol,
+            update.added.len(),
+            update.removed.len(),
+            update.level_changed.len()
+        );
+        info!(target: &FrontendMsg::TableUpdate.to_string(), "{}", serde_json::to_string(&update)?);
+    }
+    Ok(())
+}
+
+pub fn list_tables(mydir: &Path, table_cache: &TableCache) -> Result<()> {
+    let registry = TableRegistry::open(mydir)?;
+    for t in registry.tables() {
+        let cached = table_cache.load(&t.url)?;
+        let entry = TableEntry {
+            symbol: t.symbol.clone(),
+            name: t.name.clone(),
+            url: t.url.clone(),
+            charts: cached.as_ref().map(|c| c.charts().len()),
+            fetched_at: cached
+                .as_ref()
+                .map(|c| c.fetched_at().format("%F %T").to_string()),
+        };
+        info!(
+            "{}\t{}\t{}\t{} charts, fetched at {}",
+            entry.symbol,
+            entry.name,
+            entry.url,
+            entry.charts.map_or("?".to_owned(), |c| c.to_string()),
+            entry.fetched_at.as_deref().unwrap_or("-")
+        );
+        info!(target: &FrontendMsg::TableList.to_string(), "{}", serde_json::to_string(&entry)?);
+    }
+    Ok(())
+}
+
+pub fn remove_table(mydir: &Path, symbol: &str) -> Result<()> {
+    let mut registry = TableRegistry::open(mydir)?;
+    let removed = registry
+        .remove(symbol)
+        .ok_or(format!("{:?} is not registered", symbol))?;
+    info!("remove {} ({})", removed.name, removed.url);
+    registry.save()
+}
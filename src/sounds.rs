@@ -0,0 +1,85 @@
+use crate::bms::{BmsChart, Branches};
+use crate::bmson::Bmson;
+use crate::chart_hashes::is_bmson;
+use crate::errors::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// players look for these extensions when the file in #WAV doesn't exist
+const SOUND_EXTENSIONS: &[&str] = &["wav", "ogg", "flac", "mp3"];
+
+/// sound files referred by #WAV or sound_channels of the chart
+pub fn referenced_sounds(chart: &Path) -> Result<Vec<String>> {
+    let sounds = if is_bmson(chart) {
+        Bmson::from_path(chart)?
+            .sound_channels
+            .into_iter()
+            .map(|c| c.name)
+            .collect()
+    } else {
+        BmsChart::from_path_with(chart, &Branches::All)?
+            .wav
+            .into_values()
+            .collect()
+    };
+    Ok(sounds)
+}
+
+// lowercase path without the extension, e.g.) "Keys\A.WAV" -> "keys/a"
+fn sound_key(name: &str) -> String {
+    let name = name.trim().replace('\\', "/").to_lowercase();
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.contains('/') => stem.to_owned(),
+        _ => name,
+    }
+}
+
+/// sound files in a song folder, matched like players do
+pub struct SoundFiles {
+    // key -> path relative to the folder
+    files: HashMap<String, PathBuf>,
+}
+
+impl SoundFiles {
+    pub fn new(dir: &Path) -> Self {
+        let mut files = HashMap::new();
+        collect_sounds(dir, "", &mut files);
+        SoundFiles { files }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.files.contains_key(&sound_key(name))
+    }
+
+    /// the file played for the name, relative to the folder
+    pub fn path(&self, name: &str) -> Option<&Path> {
+        self.files.get(&sound_key(name)).map(|p| p.as_path())
+    }
+
+    /// ratio of the sounds found in the folder
+    pub fn coverage(&self, names: &[String]) -> f64 {
+        if names.is_empty() {
+            return 0.0;
+        }
+        let found = names.iter().filter(|n| self.contains(n)).count();
+        found as f64 / names.len() as f64
+    }
+}
+
+fn collect_sounds(dir: &Path, prefix: &str, files: &mut HashMap<String, PathBuf>) {
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        if path.is_dir() {
+            collect_sounds(&path, &format!("{}{}/", prefix, name), files);
+        } else if path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .is_some_and(|e| SOUND_EXTENSIONS.contains(&e.as_str()))
+        {
+            let relative = format!("{}{}", prefix, name);
+            files.insert(sound_key(&relative), PathBuf::from(relative));
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::errors::Result;
+use crate::fsutil;
+use crate::table_loader::TableLoader;
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CACHE_DIR_NAME: &str = "tables";
+
+/// fetched difficulty tables stored in mydir, keyed by url
+pub struct TableCache {
+    dir: PathBuf,
+    offline: bool,
+}
+
+impl TableCache {
+    pub fn new(root: &Path, offline: bool) -> Self {
+        TableCache {
+            dir: fsutil::app_dir(root).join(CACHE_DIR_NAME),
+            offline,
+        }
+    }
+
+    /// use cached tables only, without fetching
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:x}.json", Sha256::digest(url.as_bytes())))
+    }
+
+    pub fn load(&self, url: &str) -> Result<Option<TableLoader>> {
+        let path = self.path(url);
+        if !path.exists() {
+            return Ok(None);
+        }
+        debug!("load cached table {:?} from {:?}", url, path);
+        let table = serde_json::from_slice(&fs::read(path)?)?;
+        Ok(Some(table))
+    }
+
+    pub fn store(&self, url: &str, table: &TableLoader) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(url);
+        debug!("cache table {:?} to {:?}", url, path);
+        fs::write(path, serde_json::to_vec(table)?)?;
+        Ok(())
+    }
+}
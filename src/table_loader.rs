@@ -1,37 +1,336 @@
+use crate::encoding;
 use crate::errors::Result;
+use crate::table_cache::TableCache;
 
-use serde::Deserialize;
+use chrono::{DateTime, Local, TimeZone, Utc};
+use log::{debug, info, warn};
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::fs;
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ScoreData {
     pub title: String,
+    #[serde(default)]
+    pub artist: String,
     pub url: String,
     #[serde(default)]
     pub url_diff: String,
+    #[serde(default)]
     pub sha256: String,
+    #[serde(default)]
+    pub md5: String,
+    #[serde(deserialize_with = "string_or_number")]
     pub level: String,
     // #[serde(default)]
     // pub comment: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[allow(dead_code)]
+pub struct Course {
+    pub name: String,
+    #[serde(default)]
+    pub md5: Vec<String>,
+    #[serde(default)]
+    pub sha256: Vec<String>,
+    #[serde(default)]
+    pub constraint: Vec<String>,
+}
+
+/// header.json of a difficulty table
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TableHeader {
+    pub name: String,
+    pub symbol: String,
+    #[serde(default)]
+    pub data_url: String,
+    #[serde(default, deserialize_with = "strings_or_numbers")]
+    pub level_order: Vec<String>,
+    #[serde(default, deserialize_with = "courses")]
+    pub course: Vec<Course>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrNumber {
+    String(String),
+    Number(serde_json::Number),
+}
+
+impl From<StringOrNumber> for String {
+    fn from(v: StringOrNumber) -> Self {
+        match v {
+            StringOrNumber::String(s) => s,
+            StringOrNumber::Number(n) => n.to_string(),
+        }
+    }
+}
+
+// some tables write levels as numbers
+fn string_or_number<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<String, D::Error> {
+    StringOrNumber::deserialize(d).map(String::from)
+}
+
+fn strings_or_numbers<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<Vec<String>, D::Error> {
+    Vec::<StringOrNumber>::deserialize(d).map(|v| v.into_iter().map(String::from).collect())
+}
+
+// course is either a list of courses or a list of lists of courses
+fn courses<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<Course>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Courses {
+        Nested(Vec<Vec<Course>>),
+        Flat(Vec<Course>),
+    }
+    Ok(match Courses::deserialize(d)? {
+        Courses::Nested(v) => v.into_iter().flatten().collect(),
+        Courses::Flat(v) => v,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum LevelChunk {
+    Number(u64),
+    Text(String),
+}
+
+// "10" > "9", "★★10" > "★★9"
+fn natural_key(s: &str) -> Vec<LevelChunk> {
+    let mut chunks = vec![];
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        let is_digit = c.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (chunk, r) = rest.split_at(end);
+        chunks.push(match chunk.parse() {
+            Ok(n) if is_digit => LevelChunk::Number(n),
+            _ => LevelChunk::Text(chunk.to_owned()),
+        });
+        rest = r;
+    }
+    chunks
+}
+
+/// a level of a difficulty table.
+/// ordered by level_order of the header, and naturally for levels not in it
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TableLevel {
+    order: usize,
+    key: Vec<LevelChunk>,
+    name: String,
+}
+
+impl TableLevel {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Display for TableLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct TableLoader {
+    header: TableHeader,
     charts: Vec<ScoreData>,
+    /// seconds since UNIX epoch
+    fetched_at: i64,
+}
+
+/// url, file:// url or local path
+pub fn source_url(source: &str) -> Result<Url> {
+    match Url::parse(source) {
+        Ok(url) if ["http", "https", "file"].contains(&url.scheme()) => Ok(url),
+        // includes windows paths such as C:\...
+        _ => {
+            let path = fs::canonicalize(source).map_err(|e| {
+                format!(
+                    "{:?} is not a URL, a registered table symbol or an existing file: {}",
+                    source, e
+                )
+            })?;
+            Url::from_file_path(path).map_err(|_| format!("invalid table path {:?}", source).into())
+        }
+    }
+}
+
+fn fetch(url: &Url) -> Result<String> {
+    debug!("fetch {}", url);
+    if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|_| format!("invalid table path {}", url))?;
+        return Ok(encoding::decode(&fs::read(path)?, None).text);
+    }
+    Ok(reqwest::blocking::get(url.clone())?
+        .error_for_status()?
+        .text()?)
+}
+
+/// header.json url from <meta name="bmstable" content="...">
+fn find_header_url(html: &str) -> Option<String> {
+    let meta = Regex::new(r"(?is)<meta\s[^>]*>").unwrap();
+    let name = Regex::new(r#"(?i)name\s*=\s*["']?bmstable["'\s/>]"#).unwrap();
+    let content = Regex::new(r#"(?i)content\s*=\s*["']([^"']*)["']"#).unwrap();
+    let href = meta
+        .find_iter(html)
+        .map(|m| m.as_str())
+        .filter(|tag| name.is_match(tag))
+        .find_map(|tag| content.captures(tag).map(|c| c[1].trim().to_owned()));
+    href
 }
 
 impl TableLoader {
-    pub fn new(url: &str) -> Result<Self> {
-        let url = match url {
-            _ if url.ends_with("table.html") => url.replace("table.html", "score.json"),
-            _ => url.to_owned(),
-        };
+    /// source is table.html, header.json or the data json of a difficulty table.
+    /// it can be a local path or a file:// url as well.
+    /// fetched tables are cached, and the cache is used if offline or the fetch fails.
+    pub fn new(source: &str, cache: &TableCache) -> Result<Self> {
+        let url = source_url(source)?;
+        if cache.offline() && url.scheme() != "file" {
+            let table = cache.load(url.as_str())?.ok_or(format!(
+                "{} is not cached. run without --offline first",
+                url
+            ))?;
+            info!(
+                "use the table cached at {}",
+                table.fetched_at().format("%F %T")
+            );
+            return Ok(table);
+        }
+
+        match Self::fetch(&url) {
+            Ok(table) => {
+                if let Err(e) = cache.store(url.as_str(), &table) {
+                    warn!("failed to cache {}: {}", url, e);
+                }
+                Ok(table)
+            }
+            Err(e) => match cache.load(url.as_str())? {
+                Some(table) => {
+                    warn!(
+                        "failed to fetch {}: {}. use the table cached at {}",
+                        url,
+                        e,
+                        table.fetched_at().format("%F %T")
+                    );
+                    Ok(table)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    fn fetch(url: &Url) -> Result<Self> {
+        let body = fetch(url)?;
+        let fetched_at = Utc::now().timestamp();
 
-        let table_charts = reqwest::blocking::get(url)?.json::<Vec<ScoreData>>()?;
+        // the data json itself, without any header
+        if let Ok(charts) = serde_json::from_str::<Vec<ScoreData>>(&body) {
+            return Ok(TableLoader {
+                header: TableHeader::default(),
+                charts,
+                fetched_at,
+            });
+        }
+
+        let (header_url, header) = match serde_json::from_str::<TableHeader>(&body) {
+            Ok(header) => (url.clone(), header),
+            Err(_) => {
+                let href = find_header_url(&body)
+                    .ok_or(format!("{} has no <meta name=\"bmstable\">", url))?;
+                let header_url = url.join(&href)?;
+                let header = serde_json::from_str::<TableHeader>(&fetch(&header_url)?)?;
+                (header_url, header)
+            }
+        };
+        if header.data_url.is_empty() {
+            return Err(format!("{} has no data_url", header_url).into());
+        }
+        let data_url = header_url.join(&header.data_url)?;
+        let charts = serde_json::from_str::<Vec<ScoreData>>(&fetch(&data_url)?)?;
 
         Ok(TableLoader {
-            charts: table_charts,
+            header,
+            charts,
+            fetched_at,
         })
     }
 
+    pub fn fetched_at(&self) -> DateTime<Local> {
+        Local.timestamp_opt(self.fetched_at, 0).unwrap()
+    }
+
+    pub fn header(&self) -> &TableHeader {
+        &self.header
+    }
+
+    pub fn level(&self, name: &str) -> TableLevel {
+        TableLevel {
+            order: self
+                .header
+                .level_order
+                .iter()
+                .position(|l| l == name)
+                .unwrap_or(usize::MAX),
+            key: natural_key(name),
+            name: name.to_owned(),
+        }
+    }
+
+    /// every level in level_order and the charts, in order
+    pub fn levels(&self) -> Vec<TableLevel> {
+        let mut levels: Vec<TableLevel> = self
+            .header
+            .level_order
+            .iter()
+            .chain(self.charts.iter().map(|sd| &sd.level))
+            .map(|l| self.level(l))
+            .collect();
+        levels.sort();
+        levels.dedup();
+        levels
+    }
+
+    /// level specified by a user. the symbol of the table can be omitted
+    pub fn parse_level(&self, s: &str) -> Result<TableLevel> {
+        let levels = self.levels();
+        let symbol = &self.header.symbol;
+        let strip = |l: &'_ str| -> String {
+            match l.strip_prefix(symbol.as_str()) {
+                Some(stripped) if !symbol.is_empty() => stripped.to_owned(),
+                _ => l.to_owned(),
+            }
+        };
+        let found = levels
+            .iter()
+            .find(|l| l.name() == s)
+            .or_else(|| levels.iter().find(|l| strip(l.name()) == strip(s)));
+        match found {
+            Some(l) => Ok(l.clone()),
+            None => Err(format!(
+                "unknown level {:?}. levels of the table: {}",
+                s,
+                levels
+                    .iter()
+                    .map(|l| l.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into()),
+        }
+    }
+
     pub fn charts(&self) -> &Vec<ScoreData> {
         &self.charts
     }
@@ -0,0 +1,80 @@
+use crate::errors::Result;
+use crate::fsutil;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const REGISTRY_FILE_NAME: &str = "tables.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegisteredTable {
+    pub symbol: String,
+    pub name: String,
+    pub url: String,
+}
+
+/// difficulty tables registered by the table command
+pub struct TableRegistry {
+    path: PathBuf,
+    tables: Vec<RegisteredTable>,
+}
+
+impl TableRegistry {
+    pub fn open(mydir: &Path) -> Result<Self> {
+        let path = fsutil::app_dir(mydir).join(REGISTRY_FILE_NAME);
+        let tables = if path.exists() {
+            serde_json::from_slice(&fs::read(&path)?)?
+        } else {
+            vec![]
+        };
+        Ok(TableRegistry { path, tables })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        debug!("save {} tables to {:?}", self.tables.len(), self.path);
+        fs::write(&self.path, serde_json::to_vec_pretty(&self.tables)?)?;
+        Ok(())
+    }
+
+    pub fn tables(&self) -> &Vec<RegisteredTable> {
+        &self.tables
+    }
+
+    /// exact match first, then case insensitive
+    pub fn find(&self, symbol: &str) -> Option<&RegisteredTable> {
+        self.tables.iter().find(|t| t.symbol == symbol).or_else(|| {
+            self.tables
+                .iter()
+                .find(|t| t.symbol.to_lowercase() == symbol.to_lowercase())
+        })
+    }
+
+    /// url of the registered table if the source is its symbol
+    pub fn resolve(&self, source: &str) -> String {
+        match self.find(source) {
+            Some(t) => {
+                debug!("{:?} is resolved to {}", source, t.url);
+                t.url.clone()
+            }
+            None => source.to_owned(),
+        }
+    }
+
+    /// add or replace the table with the same symbol
+    pub fn insert(&mut self, table: RegisteredTable) {
+        match self.tables.iter_mut().find(|t| t.symbol == table.symbol) {
+            Some(t) => *t = table,
+            None => self.tables.push(table),
+        }
+    }
+
+    pub fn remove(&mut self, symbol: &str) -> Option<RegisteredTable> {
+        let symbol = self.find(symbol)?.symbol.clone();
+        let index = self.tables.iter().position(|t| t.symbol == symbol)?;
+        Some(self.tables.remove(index))
+    }
+}
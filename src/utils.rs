@@ -1,5 +1,6 @@
 use crate::errors::Result;
 use log::{debug, info};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{fs, path::Path};
 
@@ -70,6 +71,39 @@ pub fn add_and_write_table_json(
     Ok(())
 }
 
+// if the name has '[.*]' and other strings, remove it
+pub fn remove_difficulty(name: &str) -> String {
+    let re = Regex::new(r"(?P<name>.+?)(?P<diff>\s*\[.*?\])\s*$").unwrap();
+    let caps = re.captures(name);
+    debug!("{:?}", caps);
+    caps.and_then(|cap| cap.name("name").map(|e| e.as_str()))
+        .unwrap_or(name)
+        .to_owned()
+}
+
+// sabun artists are often written as "composer / obj: someone"
+pub fn remove_obj(artist: &str) -> String {
+    let re = Regex::new(r"(?i)^(?P<name>.+?)\s*[/(（\[【]?\s*(obj|note|notes|差分)\s*[.:：].*$")
+        .unwrap();
+    re.captures(artist)
+        .and_then(|cap| cap.name("name").map(|e| e.as_str()))
+        .unwrap_or(artist)
+        .to_owned()
+}
+
+/// normalize a title or an artist for loose matching.
+/// fullwidth alphanumerics are converted to halfwidth, and spaces and symbols are removed.
+pub fn normalize_name(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
 pub fn lamp_to_id(lamp: &str) -> Result<u8> {
     match lamp {
         "AEASY" => Ok(3),